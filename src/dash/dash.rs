@@ -0,0 +1,434 @@
+//! MPEG-DASH (MPD) ダウンローダー
+//!
+//! MPD マニフェストのダウンロードと解析、Period → AdaptationSet → Representation の走査、
+//! SegmentTemplate / SegmentTimeline / SegmentList の展開、映像・音声トラックのダウンロード、
+//! および FFmpeg を使った単一 MKV へのマルチプレクスを担当
+
+use crate::httpx;
+use base64::Engine;
+use dash_mpd::{AdaptationSet, Period, Representation, MPD};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use url::Url;
+
+/// DASH ダウンローダーエラータイプ
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("http error")]
+    Http(#[from] reqwest::Error),
+    #[error("httpx error")]
+    HttpXError(#[from] httpx::DownloadError),
+    #[error("retry error")]
+    RetryError(#[from] backoff::Error<reqwest::Error>),
+    #[error("io error")]
+    IoError(#[from] std::io::Error),
+    #[error("mpd parse error")]
+    MpdParseError(#[from] dash_mpd::DashMpdError),
+    #[error("invalid manifest url")]
+    UrlParseError(#[from] url::ParseError),
+    #[error("base64 decode error")]
+    Base64Error(#[from] base64::DecodeError),
+    #[error("no suitable video representation found")]
+    NoVideoRepresentation,
+    #[error("no usable segment addressing scheme (SegmentTemplate/SegmentList/SegmentBase)")]
+    UnsupportedAddressing,
+    #[error("manifest has {0} periods; multi-period MPDs are not supported")]
+    MultiPeriodUnsupported(usize),
+    #[error("join error")]
+    JoinError(#[from] tokio::task::JoinError),
+}
+
+/// 1つの Representation から展開された、ダウンロード可能な具体的なセグメント URL 群
+struct SegmentPlan {
+    /// 初期化セグメント（存在する場合。data: URL の場合もある）
+    initialization: Option<String>,
+    /// メディアセグメントの URL リスト（再生順）
+    media: Vec<String>,
+}
+
+/// MPEG-DASH ダウンローダー
+///
+/// HTTP クライアントを使用して DASH ストリームをダウンロードし、以下をサポート：
+/// - MPD マニフェストの解析（Period → AdaptationSet → Representation）
+/// - 帯域幅に基づく最良の映像・音声 Representation の選択
+/// - SegmentTemplate（$Number$/$Time$/$RepresentationID$ 置換）、SegmentTimeline、SegmentList の展開
+/// - FFmpeg を使った映像・音声トラックの単一 MKV へのマルチプレクス
+pub struct DASHDownloader {
+    /// HTTP クライアント
+    hc: Arc<httpx::HttpXClient>,
+}
+
+impl DASHDownloader {
+    /// 新しい DASH ダウンローダーを作成
+    pub fn new(hc: Arc<httpx::HttpXClient>) -> Self {
+        Self { hc }
+    }
+
+    /// DASH ストリームをダウンロード
+    ///
+    /// これは外部に提供されるパブリックインターフェースで、`HLSDownloader::download` と対になる
+    ///
+    /// # 引数
+    /// - `mpd_url`: MPD マニフェストの URL
+    /// - `output`: 出力ファイルパス（MKV）
+    /// - `ffmpeg_args`: FFmpeg の追加パラメータ（メタデータ、添付ファイルなど）
+    pub async fn download<P: AsRef<Path>>(
+        &mut self,
+        mpd_url: &str,
+        output: P,
+        ffmpeg_args: Option<&[&str]>,
+    ) -> Result<(), Error> {
+        let output = output.as_ref();
+
+        // ===== ステップ 1: マニフェストをダウンロードして解析 =====
+        let resp = self.hc.get_with_retry(mpd_url, None).await?;
+        let manifest_url = resp.url().clone();
+        let body = resp.text().await?;
+        let mpd: MPD = dash_mpd::parse(&body)?;
+
+        // `select_best_representation` は単一 Period を前提に最良の Representation を選ぶ。
+        // 複数 Period（広告挿入・結合コンテンツなど）はセグメントの結合/マルチプレクスが
+        // 必要になり未対応のため、ここで明示的にエラーにする
+        if mpd.periods.len() > 1 {
+            return Err(Error::MultiPeriodUnsupported(mpd.periods.len()));
+        }
+
+        // ===== ステップ 2: 最良の映像・音声 Representation を選択 =====
+        let (video, video_base, video_period_secs) =
+            Self::select_best_representation(&mpd, &manifest_url, "video")
+                .ok_or(Error::NoVideoRepresentation)?;
+        let audio = Self::select_best_representation(&mpd, &manifest_url, "audio");
+
+        // ===== ステップ 3: セグメント URL を展開してダウンロード =====
+        let video_plan = Self::expand_segments(&video.0, &video_base, &video.1, video_period_secs)?;
+        let video_path = self.download_track(&video_plan).await?;
+
+        let audio_path = if let Some((rep, base, period_secs)) = audio {
+            let plan = Self::expand_segments(&rep.0, &base, &rep.1, period_secs)?;
+            Some(self.download_track(&plan).await?)
+        } else {
+            None
+        };
+
+        // ===== ステップ 4: FFmpeg で映像・音声を単一 MKV にマルチプレクス =====
+        let status = Self::spawn_mux_ffmpeg(
+            video_path.path(),
+            audio_path.as_ref().map(|f| f.path()),
+            output,
+            ffmpeg_args,
+        )
+        .await?;
+
+        if !status.success() {
+            return Err(Error::IoError(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("ffmpeg exited with status: {}", status),
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// 指定した `content_type_prefix`（"video" または "audio"）に合致する Representation のうち、
+    /// 最も帯域幅の高いものを AdaptationSet を横断して選択する
+    ///
+    /// `mpd` は単一 Period であることが呼び出し側（`download`）で保証されている前提（複数 Period の
+    /// MPD は未対応としてエラーになる）。そのため Period を横断した選択は行わない
+    ///
+    /// # 戻り値
+    /// `((Representation, AdaptationSet), BaseURL, Period の総時間（秒）)` のタプル。BaseURL は
+    /// MPD/Period/AdaptationSet レベルの `BaseURL` をマニフェスト URL に対して解決した結果
+    fn select_best_representation(
+        mpd: &MPD,
+        manifest_url: &Url,
+        content_type_prefix: &str,
+    ) -> Option<((Representation, AdaptationSet), Url, Option<f64>)> {
+        let mpd_base = Self::resolve_base_url(manifest_url, mpd.base_url.first().map(|b| b.base.as_str()));
+
+        mpd.periods
+            .iter()
+            .flat_map(|period: &Period| {
+                let period_base =
+                    Self::resolve_base_url(&mpd_base, period.base_url.first().map(|b| b.base.as_str()));
+                let period_secs = Self::period_duration_secs(mpd, period);
+                period.adaptations.iter().flat_map(move |a| {
+                    let adaptation_base = Self::resolve_base_url(
+                        &period_base,
+                        a.base_url.first().map(|b| b.base.as_str()),
+                    );
+                    a.representations
+                        .iter()
+                        .filter(|r| Self::is_content_type(a, r, content_type_prefix))
+                        .map(move |r| ((r.clone(), a.clone()), adaptation_base.clone(), period_secs))
+                })
+            })
+            .max_by_key(|((r, _), _, _)| r.bandwidth.unwrap_or(0))
+    }
+
+    /// Period の総時間（秒）を取得する。Period 自身の `duration` がなければ
+    /// MPD 全体の `mediaPresentationDuration` にフォールバックする
+    fn period_duration_secs(mpd: &MPD, period: &Period) -> Option<f64> {
+        period
+            .duration
+            .or(mpd.mediaPresentationDuration)
+            .map(|d| d.as_secs_f64())
+    }
+
+    /// Representation/AdaptationSet の `mimeType`/`contentType` から、映像か音声かを判定
+    fn is_content_type(
+        adaptation: &AdaptationSet,
+        representation: &Representation,
+        content_type_prefix: &str,
+    ) -> bool {
+        let mime = representation
+            .mimeType
+            .as_deref()
+            .or(adaptation.mimeType.as_deref())
+            .unwrap_or("");
+        if mime.starts_with(content_type_prefix) {
+            return true;
+        }
+        adaptation
+            .contentType
+            .as_deref()
+            .map(|ct| ct == content_type_prefix)
+            .unwrap_or(false)
+    }
+
+    /// `base` 相対 URL を `relative` に対して結合する。`relative` が絶対 URL であればそれをそのまま使う
+    fn resolve_base_url(base: &Url, relative: Option<&str>) -> Url {
+        match relative {
+            Some(rel) => base.join(rel).unwrap_or_else(|_| base.clone()),
+            None => base.clone(),
+        }
+    }
+
+    /// Representation のセグメントアドレッシング方式（SegmentTemplate/SegmentList/SegmentBase）を
+    /// 具体的な URL のリストに展開する
+    fn expand_segments(
+        representation: &Representation,
+        base_url: &Url,
+        adaptation: &AdaptationSet,
+        period_duration_secs: Option<f64>,
+    ) -> Result<SegmentPlan, Error> {
+        let rep_id = representation.id.as_deref().unwrap_or_default();
+
+        // SegmentTemplate は Representation → AdaptationSet の順でフォールバック
+        if let Some(tmpl) = representation
+            .SegmentTemplate
+            .as_ref()
+            .or(adaptation.SegmentTemplate.as_ref())
+        {
+            let initialization = tmpl
+                .initialization
+                .as_ref()
+                .map(|s| Self::resolve_segment_url(base_url, &Self::expand_placeholders(s, rep_id, None, None)));
+
+            let start_number = tmpl.startNumber.unwrap_or(1);
+            let mut media = Vec::new();
+
+            if let Some(timeline) = &tmpl.SegmentTimeline {
+                // SegmentTimeline: 各 S 要素が表す時刻 `t` に基づいて $Time$/$Number$ を展開
+                let mut number = start_number;
+                let mut time: i64 = 0;
+                for s in &timeline.segments {
+                    if let Some(t) = s.t {
+                        time = t;
+                    }
+                    let repeat = s.r.unwrap_or(0).max(0);
+                    for _ in 0..=repeat {
+                        if let Some(media_tmpl) = &tmpl.media {
+                            let url = Self::expand_placeholders(
+                                media_tmpl,
+                                rep_id,
+                                Some(number),
+                                Some(time),
+                            );
+                            media.push(Self::resolve_segment_url(base_url, &url));
+                        }
+                        time += s.d;
+                        number += 1;
+                    }
+                }
+            } else if let Some(media_tmpl) = &tmpl.media {
+                // SegmentTimeline がない固定長 duration の場合、Period の総時間から総セグメント数を見積もる
+                // Period の時間が取得できない場合は安全側に倒し、最初の1セグメントのみ生成する
+                let duration = tmpl.duration.unwrap_or(0).max(1);
+                let timescale = tmpl.timescale.unwrap_or(1).max(1);
+                let segment_count = period_duration_secs
+                    .map(|secs| ((secs * timescale as f64 / duration as f64).ceil() as u64).max(1))
+                    .unwrap_or(1);
+
+                for n in start_number..start_number + segment_count {
+                    let url = Self::expand_placeholders(media_tmpl, rep_id, Some(n), None);
+                    media.push(Self::resolve_segment_url(base_url, &url));
+                }
+            }
+
+            return Ok(SegmentPlan {
+                initialization,
+                media,
+            });
+        }
+
+        // SegmentList: 明示的な URL のリストを直接使う
+        if let Some(list) = representation
+            .SegmentList
+            .as_ref()
+            .or(adaptation.SegmentList.as_ref())
+        {
+            let initialization = list
+                .Initialization
+                .as_ref()
+                .and_then(|i| i.sourceURL.as_deref())
+                .map(|u| Self::resolve_segment_url(base_url, u));
+            let media = list
+                .segment_urls
+                .iter()
+                .filter_map(|s| s.media.as_deref())
+                .map(|u| Self::resolve_segment_url(base_url, u))
+                .collect();
+            return Ok(SegmentPlan {
+                initialization,
+                media,
+            });
+        }
+
+        // SegmentBase のみ（単一ファイルを byte-range で分割配信）の場合、BaseURL そのものを1本のセグメントとして扱う
+        if representation.SegmentBase.is_some() {
+            return Ok(SegmentPlan {
+                initialization: None,
+                media: vec![base_url.as_str().to_string()],
+            });
+        }
+
+        Err(Error::UnsupportedAddressing)
+    }
+
+    /// `$RepresentationID$`/`$Number$`/`$Number%0Nd$`/`$Time$`/`$$` のプレースホルダーを展開
+    fn expand_placeholders(
+        template: &str,
+        representation_id: &str,
+        number: Option<u64>,
+        time: Option<i64>,
+    ) -> String {
+        let mut out = template.replace("$RepresentationID$", representation_id);
+        out = Self::expand_numbered_placeholder(&out, "Number", number);
+        if let Some(t) = time {
+            out = Self::expand_numbered_placeholder(&out, "Time", Some(t as u64));
+        }
+        out.replace("$$", "$")
+    }
+
+    /// `$Name$` または `$Name%0Nd$`（ゼロ埋め幅指定）形式のプレースホルダーを展開する
+    fn expand_numbered_placeholder(input: &str, name: &str, value: Option<u64>) -> String {
+        let Some(value) = value else {
+            return input.to_string();
+        };
+        let plain = format!("${}$", name);
+        let mut out = input.replace(&plain, &value.to_string());
+
+        // `$Number%05d$` のような幅指定付きプレースホルダーを処理
+        let prefix = format!("${}%0", name);
+        while let Some(start) = out.find(&prefix) {
+            if let Some(end_rel) = out[start..].find("d$") {
+                let end = start + end_rel + 2;
+                let width_str = &out[start + prefix.len()..start + end_rel];
+                if let Ok(width) = width_str.parse::<usize>() {
+                    let formatted = format!("{:0width$}", value, width = width);
+                    out.replace_range(start..end, &formatted);
+                    continue;
+                }
+            }
+            break;
+        }
+        out
+    }
+
+    /// セグメント URL をマニフェスト/BaseURL に対して解決する。`data:` URL や絶対 URL はそのまま返す
+    fn resolve_segment_url(base_url: &Url, candidate: &str) -> String {
+        if candidate.starts_with("data:") {
+            return candidate.to_string();
+        }
+        base_url
+            .join(candidate)
+            .map(|u| u.to_string())
+            .unwrap_or_else(|_| candidate.to_string())
+    }
+
+    /// 1トラック分のセグメント（初期化セグメント + メディアセグメント）を順番にダウンロードし、
+    /// 1つの一時ファイルに連結する
+    async fn download_track(&self, plan: &SegmentPlan) -> Result<tempfile::NamedTempFile, Error> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(tmp_file.path())
+            .await?;
+
+        if let Some(init) = &plan.initialization {
+            let bytes = self.fetch_segment(init).await?;
+            file.write_all(&bytes).await?;
+        }
+
+        for url in &plan.media {
+            let bytes = self.fetch_segment(url).await?;
+            file.write_all(&bytes).await?;
+        }
+        file.flush().await?;
+
+        Ok(tmp_file)
+    }
+
+    /// 1つのセグメントをダウンロードする。`data:` URL の場合は base64 デコードのみ行う
+    async fn fetch_segment(&self, url: &str) -> Result<Vec<u8>, Error> {
+        if let Some(data) = url.strip_prefix("data:") {
+            let (_, payload) = data.split_once(',').unwrap_or(("", data));
+            return Ok(base64::engine::general_purpose::STANDARD.decode(payload)?);
+        }
+
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        self.hc
+            .download_with_retry(url, tmp_file.path(), false, None, None)
+            .await?;
+        Ok(tokio::fs::read(tmp_file.path()).await?)
+    }
+
+    /// 映像・音声トラックを単一の MKV にマルチプレクスする FFmpeg プロセスを起動して完了を待機
+    ///
+    /// # 引数
+    /// - `video`: 映像トラックのファイルパス
+    /// - `audio`: 音声トラックのファイルパス（存在しない場合は映像のみ）
+    /// - `output`: 出力ファイルパス
+    /// - `output_args`: FFmpeg の追加パラメータ（メタデータ、添付ファイルなど）
+    async fn spawn_mux_ffmpeg(
+        video: &Path,
+        audio: Option<&Path>,
+        output: &Path,
+        output_args: Option<&[&str]>,
+    ) -> Result<std::process::ExitStatus, Error> {
+        let mut cmd = Command::new("ffmpeg");
+        cmd.args(["-hide_banner", "-loglevel", "error", "-nostats", "-y"]);
+        cmd.args(["-i"]).arg(video);
+        if let Some(audio) = audio {
+            cmd.args(["-i"]).arg(audio);
+        }
+        cmd.args(["-c", "copy"]);
+
+        if let Some(args) = output_args {
+            cmd.args(args);
+        }
+
+        cmd.arg(output);
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+
+        Ok(cmd.spawn()?.wait().await?)
+    }
+}
@@ -1,6 +1,6 @@
 //! HLS (HTTP Live Streaming) ダウンローダー
 //!
-//! M3U8 プレイリストのダウンロードと解析、MPEG-TS セグメントのダウンロード、
+//! M3U8 プレイリストのダウンロードと解析、MPEG-TS / fMP4 (CMAF) セグメントのダウンロード、
 //! AES-128 暗号化の処理、および FFmpeg へのデータストリーミングを担当
 
 use crate::httpx;
@@ -9,26 +9,156 @@ use cbc::Decryptor;
 use cipher::{BlockDecryptMut, KeyIvInit, block_padding::Pkcs7};
 use kdam::{BarExt, tqdm};
 use m3u8_rs;
+use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 use tokio::fs::OpenOptions;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::Command;
 use tokio::sync::mpsc as tokio_mpsc;
+use tokio::sync::Semaphore;
 use tokio::task::JoinError;
 
+/// セグメント同時ダウンロード数のデフォルト値
+const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Master Playlist から選択するバリアントの画質
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quality {
+    /// 帯域幅が最大のバリアント（デフォルト）
+    #[default]
+    Best,
+    /// 帯域幅が最小のバリアント
+    Worst,
+    /// 指定した高さ（px）を超えない範囲でもっとも解像度が高いバリアント
+    /// （該当するものがなければ、もっとも近い解像度にフォールバック）
+    Height(u64),
+    /// 映像トラックを持たない、音声のみのバリアントのうち帯域幅が最大のもの
+    AudioOnly,
+}
+
+/// `Quality` のパースエラー
+#[derive(Error, Debug)]
+#[error("invalid quality: {0} (expected \"best\", \"worst\", \"audio\", or e.g. \"720p\"/\"1080\")")]
+pub struct ParseQualityError(String);
+
+/// ダウンロードの進捗・ライフサイクルイベント
+///
+/// `HLSDownloader` はセグメントのダウンロード中にこれらのイベントをコールバックへ通知する。
+/// これにより、GUI やカスタムロガーへ進捗を報告する埋め込み利用が可能になる
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// セグメントのダウンロードを開始した
+    SegmentStarted { index: usize, total: usize },
+    /// セグメントのダウンロードが完了した
+    SegmentFinished { index: usize, bytes: u64 },
+    /// 出力ファイルをオープンした（埋め込み側が進行中のファイルを追跡できるようにする）
+    OutputFileOpened { path: PathBuf },
+    /// ダウンロードが完了した
+    Completed,
+}
+
+/// 進捗・ライフサイクルイベントを受け取るコールバックの型
+pub type ProgressCallback = dyn Fn(Event) + Send + Sync;
+
+/// 字幕トラックを MKV へ埋め込む際の出力コーデック
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleCodec {
+    /// SubRip (.srt) — Matroska 出力で一般的に使われる
+    Srt,
+    /// MOV text — MP4/ISOBMFF 系コンテナ向け
+    MovText,
+    /// Advanced SubStation Alpha (.ass) — 弾幕コメントなど装飾付き字幕向け
+    Ass,
+}
+
+impl SubtitleCodec {
+    /// `-c:s` に渡す ffmpeg のコーデック名
+    fn as_ffmpeg_arg(&self) -> &'static str {
+        match self {
+            SubtitleCodec::Srt => "srt",
+            SubtitleCodec::MovText => "mov_text",
+            SubtitleCodec::Ass => "ass",
+        }
+    }
+}
+
+/// 埋め込み対象の字幕トラック
+///
+/// `url` は WebVTT セグメントを列挙する Media Playlist（`EXT-X-MEDIA TYPE=SUBTITLES` が指すもの）か、
+/// 単体の字幕ファイルのいずれかを指す
+#[derive(Debug, Clone)]
+pub struct SubtitleTrack {
+    /// 字幕ソースの URL
+    pub url: String,
+    /// `-metadata:s:s:N language=...` に設定する言語コード（BCP 47 など）
+    pub language: Option<String>,
+    /// `-metadata:s:s:N title=...` に設定するトラック名（例：マスタープレイリストのレンディション名）
+    pub title: Option<String>,
+    /// 出力コンテナに埋め込む際のコーデック
+    pub codec: SubtitleCodec,
+}
+
+impl std::str::FromStr for Quality {
+    type Err = ParseQualityError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "best" => Ok(Quality::Best),
+            "worst" => Ok(Quality::Worst),
+            "audio" | "audio-only" | "audioonly" => Ok(Quality::AudioOnly),
+            other => other
+                .trim_end_matches('p')
+                .parse::<u64>()
+                .map(Quality::Height)
+                .map_err(|_| ParseQualityError(s.to_string())),
+        }
+    }
+}
+
 /// HLS ダウンローダー
 ///
 /// HTTP クライアントを使用して HLS ストリームをダウンロードし、以下をサポート：
 /// - Master Playlist と Media Playlist の解析
 /// - AES-128 暗号化セグメントの復号化
+/// - 複数セグメントの並列ダウンロード（FFmpeg へは順序通りに配信）
 /// - FFmpeg へのストリーミング転送によるパッケージング
 pub struct HLSDownloader {
     /// HTTP クライアント
     hc: Arc<httpx::HttpXClient>,
+    /// 同時にダウンロードするセグメント数の上限
+    concurrency: usize,
+    /// Master Playlist から選択するバリアントの画質
+    quality: Quality,
+    /// 進捗・ライフサイクルイベントの通知先
+    progress: Arc<ProgressCallback>,
+}
+
+/// セグメントのコンテナ形式
+///
+/// EXT-X-MAP（初期化セグメント）が存在する場合は fMP4/CMAF、存在しない場合は従来の MPEG-TS とみなす
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SegmentContainer {
+    /// MPEG-TS（従来形式）
+    MpegTs,
+    /// fMP4 / CMAF（EXT-X-MAP の moov + 各セグメントの moof/mdat）
+    Fmp4,
+}
+
+impl SegmentContainer {
+    /// Media Playlist を調べて使用されているコンテナ形式を判定
+    ///
+    /// いずれかのセグメントが `EXT-X-MAP` を持つ場合は fMP4 とみなす
+    fn detect(pl: &m3u8_rs::MediaPlaylist) -> Self {
+        if pl.segments.iter().any(|s| s.map.is_some()) {
+            SegmentContainer::Fmp4
+        } else {
+            SegmentContainer::MpegTs
+        }
+    }
 }
 
 /// HLS 下载器错误类型
@@ -51,38 +181,156 @@ pub enum Error {
 }
 
 impl HLSDownloader {
-    /// 新しい HLS ダウンローダーを作成
+    /// 新しい HLS ダウンローダーを作成（同時ダウンロード数はデフォルト値を使用）
     pub fn new(hc: Arc<httpx::HttpXClient>) -> Self {
-        Self { hc }
+        Self::with_concurrency(hc, DEFAULT_CONCURRENCY)
+    }
+
+    /// 同時ダウンロード数を指定して HLS ダウンローダーを作成（画質はデフォルトの `Best` を使用）
+    ///
+    /// # 引数
+    /// - `concurrency`: 同時にダウンロードするセグメント数の上限（0 は 1 として扱う）
+    pub fn with_concurrency(hc: Arc<httpx::HttpXClient>, concurrency: usize) -> Self {
+        Self::with_options(hc, concurrency, Quality::default())
+    }
+
+    /// 同時ダウンロード数と画質選択を指定して HLS ダウンローダーを作成
+    ///
+    /// デフォルトの進捗コールバックは CLI 向けにターミナルへ `tqdm` バーを描画する。
+    /// ライブラリとして組み込む場合は `set_progress_callback` で差し替え、ターミナル出力を抑制できる
+    ///
+    /// # 引数
+    /// - `concurrency`: 同時にダウンロードするセグメント数の上限（0 は 1 として扱う）
+    /// - `quality`: Master Playlist から選択するバリアントの画質
+    pub fn with_options(hc: Arc<httpx::HttpXClient>, concurrency: usize, quality: Quality) -> Self {
+        Self {
+            hc,
+            concurrency: concurrency.max(1),
+            quality,
+            progress: Self::default_progress_callback(),
+        }
+    }
+
+    /// Master Playlist から選択するバリアントの画質（現在の設定値）を取得する
+    pub fn quality(&self) -> Quality {
+        self.quality
+    }
+
+    /// 進捗・ライフサイクルイベントのコールバックを差し替える
+    ///
+    /// GUI やカスタムロガーへ進捗を報告したい埋め込み利用者はこれを呼び、デフォルトの
+    /// `tqdm` ターミナル出力を独自のハンドラに置き換えることができる
+    pub fn set_progress_callback(&mut self, callback: Arc<ProgressCallback>) {
+        self.progress = callback;
+    }
+
+    /// ターミナルに `tqdm` の総合プログレスバーを描画するデフォルトの進捗コールバック
+    ///
+    /// CLI バイナリはこれをそのまま使う
+    pub fn default_progress_callback() -> Arc<ProgressCallback> {
+        let bar: Mutex<Option<kdam::Bar>> = Mutex::new(None);
+        Arc::new(move |event: Event| {
+            let mut bar = bar.lock().unwrap();
+            match event {
+                Event::SegmentStarted { total, .. } => {
+                    if bar.is_none() {
+                        *bar = Some(tqdm!(
+                            total = total,
+                            desc = "Total",
+                            ncols = 80,
+                            position = 0,
+                            leave = true
+                        ));
+                    }
+                }
+                Event::SegmentFinished { .. } => {
+                    if let Some(b) = bar.as_mut() {
+                        b.update(1).expect("TODO: panic message");
+                        b.refresh().expect("TODO: panic message");
+                    }
+                }
+                Event::OutputFileOpened { path } => {
+                    log::info!("output file opened: {}", path.display());
+                }
+                Event::Completed => {
+                    if let Some(b) = bar.as_mut() {
+                        b.refresh().expect("TODO: panic message");
+                    }
+                }
+            }
+        })
     }
 
     /// FFmpeg プロセスを起動
     ///
-    /// FFmpeg は標準入力から MPEG-TS ストリームを読み取り、output_args に基づいてパッケージングする
+    /// FFmpeg は標準入力からストリームを読み取り、output_args に基づいてパッケージングする
     ///
     /// # 引数
+    /// - `container`: 標準入力に流れるセグメントのコンテナ形式（MPEG-TS または fMP4）
+    /// - `subtitles`: 追加の字幕入力ファイルとその `SubtitleTrack` 設定
     /// - `output`: 出力ファイルパス
     /// - `output_args`: FFmpeg の追加パラメータ（メタデータ、添付ファイルなど）
-    fn spawn_ffmpeg<I, S, P>(output: P, output_args: Option<I>) -> tokio::process::Child
+    fn spawn_ffmpeg<I, S, P>(
+        container: SegmentContainer,
+        subtitles: &[(PathBuf, SubtitleTrack)],
+        output: P,
+        output_args: Option<I>,
+    ) -> tokio::process::Child
     where
         P: AsRef<Path>,
         I: IntoIterator<Item = S>,
         S: AsRef<OsStr>,
     {
         let mut cmd = Command::new("ffmpeg");
-        // 基本パラメータ：バナーを非表示、エラーログレベル、パイプから MPEG-TS を読み取り
+        // 基本パラメータ：バナーを非表示、エラーログレベル
         cmd.args([
             "-hide_banner", // バージョン情報を非表示
             "-loglevel",
             "error",    // エラーのみ表示
             "-nostats", // 統計情報を表示しない
             "-y",       // 出力ファイルを上書き
-            "-f",
-            "mpegts", // 入力形式は MPEG-TS
-            "-i",
-            "pipe:0", // 標準入力から読み取り
         ]);
 
+        // 入力形式：MPEG-TS は `-f mpegts`、fMP4/CMAF（初期化セグメントの moov + 各 moof/mdat）は
+        // `-f mp4` を明示指定する
+        match container {
+            SegmentContainer::MpegTs => {
+                cmd.args(["-f", "mpegts"]);
+            }
+            SegmentContainer::Fmp4 => {
+                cmd.args(["-f", "mp4"]);
+            }
+        }
+
+        cmd.args([
+            "-i", "pipe:0", // 標準入力から読み取り
+        ]);
+
+        // 字幕トラックを追加入力として渡す（各入力インデックスは 1 から開始）
+        for (path, _) in subtitles {
+            cmd.arg("-i").arg(path);
+        }
+
+        // 字幕が1つ以上ある場合、メイン入力(0)と全字幕入力を明示的にマップし、
+        // トラックごとにコーデックと言語メタデータを設定する
+        if !subtitles.is_empty() {
+            cmd.args(["-map", "0"]);
+            for (i, (_, track)) in subtitles.iter().enumerate() {
+                let input_index = i + 1;
+                cmd.args(["-map", &input_index.to_string()]);
+                cmd.args([
+                    format!("-c:s:{}", i),
+                    track.codec.as_ffmpeg_arg().to_string(),
+                ]);
+                if let Some(ref lang) = track.language {
+                    cmd.args([format!("-metadata:s:s:{}", i), format!("language={}", lang)]);
+                }
+                if let Some(ref title) = track.title {
+                    cmd.args([format!("-metadata:s:s:{}", i), format!("title={}", title)]);
+                }
+            }
+        }
+
         // 追加の出力パラメータを追加（メタデータ、添付ファイルなど）
         if let Some(args) = output_args {
             cmd.args(args);
@@ -140,55 +388,35 @@ impl HLSDownloader {
         Ok(decrypted.to_vec())
     }
 
-    /// 単一の HLS セグメントをダウンロードして処理
+    /// 単一の HLS セグメントをダウンロードして復号化する
+    ///
+    /// 並列ワーカーから呼び出されるため、`&self` ではなく所有権を持つ引数のみを取る
     ///
     /// フロー：
     /// 1. セグメントを一時ファイルにダウンロード
     /// 2. セグメントが暗号化されている場合、復号化
-    /// 3. 復号化されたデータを channel に送信し、FFmpeg が読み取れるようにする
     ///
     /// # 引数
-    /// - `frag`: M3U8 セグメント情報
-    /// - `tx`: 復号化されたデータを送信するための channel 送信側
+    /// - `hc`: HTTP クライアント
+    /// - `uri`: セグメントの URI
     /// - `key`: AES キー（セグメントが暗号化されている場合）
     /// - `iv`: 初期化ベクトル（セグメントが暗号化されている場合）
-    /// - `fragment_pb`: セグメントダウンロードのプログレスバー（オプション）
-    async fn parse_segment(
-        &mut self,
-        frag: &m3u8_rs::MediaSegment,
-        tx: &tokio_mpsc::Sender<Vec<u8>>,
-        key: Option<&[u8; 16]>,
-        iv: Option<&[u8; 16]>,
-        fragment_pb: Option<&mut kdam::Bar>,
-    ) -> Result<(), Error> {
+    ///
+    /// # 戻り値
+    /// 復号化済みのセグメントデータ
+    async fn download_segment(
+        hc: Arc<httpx::HttpXClient>,
+        uri: String,
+        key: Option<[u8; 16]>,
+        iv: Option<[u8; 16]>,
+    ) -> Result<Vec<u8>, Error> {
         // セグメントを保存する一時ファイルを作成
         let tmp_file = tempfile::NamedTempFile::new()?;
         let tmp_path = tmp_file.path();
 
-        // セグメントをダウンロード（ライフタイムの問題のため、プログレスコールバックは使用しない）
-        // プログレスバーはダウンロード完了後に更新
-        let progress_cb: Option<Box<dyn Fn(usize, u64, u64) + Send + Sync>> = None;
-
-        self.hc
-            .download_with_retry(
-                &frag.uri.as_str(),
-                tmp_path,
-                true,
-                None,
-                progress_cb.as_deref(), // レジューム機能をサポート
-            )
+        hc.download_with_retry(&uri, tmp_path, true, None, None)
             .await?;
 
-        // セグメントプログレスバーを更新（ダウンロード完了後）
-        if let Some(pb) = fragment_pb {
-            let file_size = tokio::fs::metadata(tmp_path).await?.len();
-            if pb.total == 0 && file_size > 0 {
-                pb.total = file_size as usize;
-            }
-            pb.update(file_size as usize).expect("TODO: panic message");
-            pb.refresh().expect("TODO: panic message");
-        }
-
         // ダウンロードしたファイルを読み取り
         let mut file = OpenOptions::new().read(true).open(tmp_path).await?;
         let mut buf = Vec::new();
@@ -196,14 +424,172 @@ impl HLSDownloader {
 
         // セグメントが暗号化されている場合、復号化
         let decrypted = if let (Some(key), Some(iv)) = (key, iv) {
-            Self::decrypt_hls_ts(&buf, key, iv)?
+            Self::decrypt_hls_ts(&buf, &key, &iv)?
         } else {
             buf
         };
 
-        // 復号化されたデータを channel に送信し、FFmpeg が読み取れるようにする
-        tx.send(decrypted).await?;
-        Ok(())
+        Ok(decrypted)
+    }
+
+    /// EXT-X-MAP が指す初期化セグメント（fMP4 の `moov`/トラックヘッダー）をダウンロード
+    ///
+    /// # 引数
+    /// - `map`: M3U8 の Map（初期化セグメント）情報
+    ///
+    /// # 戻り値
+    /// 初期化セグメントの生バイト列
+    async fn fetch_init_segment(&mut self, map: &m3u8_rs::Map) -> Result<Vec<u8>, Error> {
+        let tmp_file = tempfile::NamedTempFile::new()?;
+        let tmp_path = tmp_file.path();
+
+        self.hc
+            .download_with_retry(map.uri.as_str(), tmp_path, true, None, None)
+            .await?;
+
+        let mut file = OpenOptions::new().read(true).open(tmp_path).await?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).await?;
+        Ok(buf)
+    }
+
+    /// 字幕トラックをダウンロードし、ffmpeg の追加入力として渡せる1つのファイルにまとめる
+    ///
+    /// `track.url` が M3U8 Media Playlist の場合は WebVTT セグメントを順番にダウンロードして
+    /// 連結し、それ以外の場合は単体の字幕ファイルとしてそのままダウンロードする
+    ///
+    /// # 引数
+    /// - `track`: ダウンロード対象の字幕トラック
+    ///
+    /// # 戻り値
+    /// 字幕データを保持する一時ファイル
+    async fn fetch_subtitle(&self, track: &SubtitleTrack) -> Result<tempfile::NamedTempFile, Error> {
+        // `file://` はローカルに生成済みのトラック（弾幕 ASS など）を指す。HTTP 経由で取得せず、
+        // そのまま一時ファイルへコピーする
+        if let Some(local_path) = track.url.strip_prefix("file://") {
+            let tmp_file = tempfile::NamedTempFile::new()?;
+            tokio::fs::copy(local_path, tmp_file.path()).await?;
+            return Ok(tmp_file);
+        }
+
+        let resp = self.hc.get_with_retry(&track.url, None).await?;
+        let payload = resp.bytes().await?;
+
+        let tmp_file = tempfile::NamedTempFile::new()?;
+
+        match m3u8_rs::parse_playlist(&payload) {
+            Ok((_, m3u8_rs::Playlist::MediaPlaylist(pl))) => {
+                // WebVTT セグメントを列挙する Media Playlist：各セグメントは `WEBVTT` ヘッダーと
+                // `X-TIMESTAMP-MAP` オフセットを重複して持つため、単純にバイト列を連結すると
+                // ヘッダーが複数回出現しキュー時刻もセグメントローカルのままになり ffmpeg の
+                // webvtt デマルチプレクサが解釈できない。ヘッダーは一度だけ書き出し、各セグメント
+                // のキュー時刻には `X-TIMESTAMP-MAP` から算出したオフセットを適用する
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .truncate(true)
+                    .open(tmp_file.path())
+                    .await?;
+                file.write_all(b"WEBVTT\n\n").await?;
+                for seg in pl.segments.iter() {
+                    let seg_resp = self.hc.get_with_retry(&seg.uri, None).await?;
+                    let seg_bytes = seg_resp.bytes().await?;
+                    let cues = Self::normalize_webvtt_segment(&seg_bytes);
+                    file.write_all(cues.as_bytes()).await?;
+                }
+                file.flush().await?;
+            }
+            _ => {
+                // 単体の字幕ファイル：そのまま書き込む
+                tokio::fs::write(tmp_file.path(), &payload).await?;
+            }
+        }
+
+        Ok(tmp_file)
+    }
+
+    /// WebVTT セグメント1つを解析し、`X-TIMESTAMP-MAP`（MPEGTS ⇔ LOCAL のタイムスタンプ対応）から
+    /// 算出したオフセットをキューの開始・終了時刻に適用したキュー本文（ヘッダーを除く）を返す
+    ///
+    /// `X-TIMESTAMP-MAP` が無い、または解析できないセグメントはオフセット 0 として扱う
+    fn normalize_webvtt_segment(payload: &[u8]) -> String {
+        let text = String::from_utf8_lossy(payload);
+        let mut lines = text.lines();
+
+        // ヘッダーブロック（空行まで）を読み飛ばしつつ X-TIMESTAMP-MAP を探す
+        let mut offset_secs = 0.0_f64;
+        for line in lines.by_ref() {
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some(rest) = line.trim().strip_prefix("X-TIMESTAMP-MAP") {
+                let rest = rest.trim_start_matches([':', '=']).trim();
+                let mut mpegts: Option<f64> = None;
+                let mut local: Option<f64> = None;
+                for field in rest.split(',') {
+                    let field = field.trim();
+                    if let Some(v) = field.strip_prefix("MPEGTS:") {
+                        mpegts = v.trim().parse::<f64>().ok();
+                    } else if let Some(v) = field.strip_prefix("LOCAL:") {
+                        local = Self::parse_webvtt_timestamp(v.trim());
+                    }
+                }
+                if let (Some(mpegts), Some(local)) = (mpegts, local) {
+                    // MPEGTS の PTS は 90kHz クロックで刻まれる
+                    offset_secs = mpegts / 90_000.0 - local;
+                }
+            }
+        }
+
+        let mut out = String::new();
+        for line in lines {
+            if let Some((start, rest)) = line.split_once("-->") {
+                let rest = rest.trim_start();
+                let mut rest_parts = rest.splitn(2, char::is_whitespace);
+                let end = rest_parts.next().unwrap_or("");
+                let settings = rest_parts.next().unwrap_or("");
+                if let (Some(start_secs), Some(end_secs)) = (
+                    Self::parse_webvtt_timestamp(start.trim()),
+                    Self::parse_webvtt_timestamp(end),
+                ) {
+                    out.push_str(&Self::format_webvtt_timestamp(start_secs + offset_secs));
+                    out.push_str(" --> ");
+                    out.push_str(&Self::format_webvtt_timestamp(end_secs + offset_secs));
+                    if !settings.is_empty() {
+                        out.push(' ');
+                        out.push_str(settings);
+                    }
+                    out.push('\n');
+                    continue;
+                }
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push('\n');
+        out
+    }
+
+    /// `HH:MM:SS.mmm` または `MM:SS.mmm` 形式の WebVTT タイムスタンプを秒数に変換
+    fn parse_webvtt_timestamp(s: &str) -> Option<f64> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let (h, m, s): (f64, f64, f64) = match parts.as_slice() {
+            [h, m, s] => (h.parse().ok()?, m.parse().ok()?, s.parse().ok()?),
+            [m, s] => (0.0, m.parse().ok()?, s.parse().ok()?),
+            _ => return None,
+        };
+        Some(h * 3600.0 + m * 60.0 + s)
+    }
+
+    /// 秒数を WebVTT の `HH:MM:SS.mmm` 形式に変換
+    fn format_webvtt_timestamp(total_secs: f64) -> String {
+        let total_secs = total_secs.max(0.0);
+        let total_millis = (total_secs * 1000.0).round() as u64;
+        let ms = total_millis % 1000;
+        let total_secs = total_millis / 1000;
+        let h = total_secs / 3600;
+        let m = (total_secs % 3600) / 60;
+        let s = total_secs % 60;
+        format!("{:02}:{:02}:{:02}.{:03}", h, m, s, ms)
     }
 
     /// Media Playlist を解析してダウンロード
@@ -212,18 +598,21 @@ impl HLSDownloader {
     /// 1. 暗号化キーを取得（プレイリストが暗号化されている場合）
     /// 2. FFmpeg プロセスを起動
     /// 3. 復号化されたデータを FFmpeg の標準入力に書き込む非同期タスクを作成
-    /// 4. すべてのセグメントを走査し、ダウンロード、復号化して FFmpeg に送信
+    /// 4. 最大 `concurrency` 件のセグメントを並列にダウンロード・復号化し、
+    ///    playlist 順を保ったまま FFmpeg に送信
     /// 5. FFmpeg のパッケージング完了を待機
     ///
     /// # 引数
     /// - `pl`: Media Playlist オブジェクト
     /// - `output`: 出力ファイルパス
     /// - `ffmpeg_args`: FFmpeg の追加パラメータ
+    /// - `subtitles`: 埋め込む字幕トラックのリスト
     async fn parse_media_playlist(
         &mut self,
         pl: m3u8_rs::MediaPlaylist,
         output: &Path,
         ffmpeg_args: Option<&[&str]>,
+        subtitles: &[SubtitleTrack],
     ) -> Result<(), Error> {
         // ===== ステップ 1: 暗号化キーを取得 =====
         // HLS 標準では、キーは通常最初のセグメントの EXT-X-KEY タグで定義される
@@ -255,12 +644,30 @@ impl HLSDownloader {
             }
         }
 
+        // ===== fMP4/CMAF か MPEG-TS かを判定 =====
+        // EXT-X-MAP（初期化セグメント）を持つプレイリストは fMP4/CMAF とみなす
+        let container = SegmentContainer::detect(&pl);
+
+        // ===== 字幕トラックを事前にダウンロードし、ffmpeg の追加入力ファイルとして用意する =====
+        // NamedTempFile は ffmpeg の実行が終わるまで生存させる必要があるため、別途保持しておく
+        let mut subtitle_files = Vec::with_capacity(subtitles.len());
+        let mut subtitle_temp_guards = Vec::with_capacity(subtitles.len());
+        for track in subtitles {
+            let tmp_file = self.fetch_subtitle(track).await?;
+            subtitle_files.push((tmp_file.path().to_path_buf(), track.clone()));
+            subtitle_temp_guards.push(tmp_file);
+        }
+
         // ===== ステップ 2: ダウンロードタスクと FFmpeg の間でデータを渡すための channel を作成 =====
         let (tx, mut rx) = tokio_mpsc::channel::<Vec<u8>>(100);
 
         // ===== ステップ 3: FFmpeg プロセスを起動 =====
-        let mut ffmpeg: tokio::process::Child = Self::spawn_ffmpeg(output, ffmpeg_args);
+        let mut ffmpeg: tokio::process::Child =
+            Self::spawn_ffmpeg(container, &subtitle_files, output, ffmpeg_args);
         let stdin = ffmpeg.stdin.take().unwrap();
+        (self.progress)(Event::OutputFileOpened {
+            path: output.to_path_buf(),
+        });
 
         // ===== ステップ 4: 復号化されたデータを FFmpeg の標準入力に書き込む非同期タスクを作成 =====
         let writer = tokio::spawn(async move {
@@ -288,34 +695,15 @@ impl HLSDownloader {
             let _ = stdin.shutdown().await;
         });
 
-        // ===== ステップ 5: プログレスバーを作成 =====
-        // 総合プログレスバー：すべてのセグメントのダウンロード進捗を表示
-        let mut total_pb = tqdm!(
-            total = pl.segments.len(),
-            desc = "Total",
-            ncols = 80,
-            position = 0,
-            leave = true
-        );
-
-        // ===== ステップ 6: すべてのセグメントを走査し、ダウンロード、復号化して送信 =====
-        for (idx, f) in pl.segments.iter().enumerate() {
-            // セグメントプログレスバーを作成：現在のセグメントのダウンロード進捗を表示
-            let mut fragment_pb = tqdm!(
-                desc = format!("Fragment: {}", idx + 1),
-                unit = "B",
-                unit_scale = true,
-                ncols = 80,
-                position = 1,
-                leave = false
-            );
-
-            // 現在のセグメントに独自のキーがあるか確認
-            // セグメントに独自のキーがある場合、セグメントのキーを使用；それ以外はプレイリストレベルのキーを使用
+        // ===== ステップ 6: セグメントごとの暗号化キーを事前解決 =====
+        // セグメント固有のキーは稀だが、並列ダウンロードを始める前に全セグメント分を解決しておく
+        // （それ以外はプレイリストレベルのキーにフォールバック）
+        let mut seg_keys: Vec<(Option<[u8; 16]>, Option<[u8; 16]>)> =
+            Vec::with_capacity(pl.segments.len());
+        for f in pl.segments.iter() {
             let seg_key = if let Some(ref k) = f.key {
                 if let Some(ref uri) = k.uri {
                     if let m3u8_rs::KeyMethod::AES128 = k.method {
-                        // セグメント固有のキーをダウンロード
                         let key_resp = self.hc.get_with_retry(uri, None).await?;
                         let key_bytes = key_resp.bytes().await?;
                         if key_bytes.len() >= 16 {
@@ -340,26 +728,102 @@ impl HLSDownloader {
                 // セグメントにキーがない場合、プレイリストレベルのキーを使用
                 (key, iv)
             };
+            seg_keys.push(seg_key);
+        }
 
-            // セグメントをダウンロード、復号化して送信
-            self.parse_segment(
-                f,
-                &tx,
-                seg_key.0.as_ref(),
-                seg_key.1.as_ref(),
-                Some(&mut fragment_pb),
-            )
-            .await?;
-            fragment_pb.refresh().expect("TODO: panic message");
-            total_pb.update(1).expect("TODO: panic message");
+        // ===== ステップ 7: セグメントを最大 `concurrency` 件まで並列ダウンロードし、
+        // playlist 順を保ったまま FFmpeg へ配信 =====
+        // ワーカーはダウンロード完了順に結果を返すため、`next_to_send` より前のインデックスが
+        // すべて揃うまで `pending` に溜めておく並べ替えバッファを使う
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
+        let (result_tx, mut result_rx) =
+            tokio_mpsc::channel::<(usize, Result<Vec<u8>, Error>)>(pl.segments.len().max(1));
+
+        let total_segments = pl.segments.len();
+        let mut workers = Vec::with_capacity(total_segments);
+        for (idx, f) in pl.segments.iter().enumerate() {
+            let hc = self.hc.clone();
+            let uri = f.uri.clone();
+            let (key, iv) = seg_keys[idx];
+            let sem = semaphore.clone();
+            let result_tx = result_tx.clone();
+            let progress = self.progress.clone();
+            workers.push(tokio::spawn(async move {
+                // 同時ダウンロード数を `concurrency` に制限
+                let _permit = sem.acquire_owned().await.expect("semaphore closed");
+                progress(Event::SegmentStarted {
+                    index: idx,
+                    total: total_segments,
+                });
+                let result = Self::download_segment(hc, uri, key, iv).await;
+                let _ = result_tx.send((idx, result)).await;
+            }));
         }
-        total_pb.refresh().expect("TODO: panic message");
+        drop(result_tx); // 各ワーカーが clone を保持しているので、ここで閉じても安全
+
+        let mut pending: HashMap<usize, Vec<u8>> = HashMap::new();
+        let mut next_to_send = 0usize;
+        let mut last_map_uri: Option<String> = None;
+        let mut first_err: Option<Error> = None;
+
+        while let Some((idx, result)) = result_rx.recv().await {
+            match result {
+                Ok(data) => {
+                    (self.progress)(Event::SegmentFinished {
+                        index: idx,
+                        bytes: data.len() as u64,
+                    });
+                    pending.insert(idx, data);
+                }
+                Err(e) => {
+                    // 最初のエラーを記録し、残りのワーカーの完了を待たずに即座にループを抜ける
+                    first_err.get_or_insert(e);
+                    break;
+                }
+            }
 
-        // ===== ステップ 7: channel を閉じ、すべてのデータの書き込み完了を待機 =====
+            // 連続するインデックスが揃っている分だけ、playlist 順で FFmpeg へ流す
+            while first_err.is_none() {
+                let Some(data) = pending.remove(&next_to_send) else {
+                    break;
+                };
+
+                // fMP4/CMAF の場合、moov/トラックヘッダーを含む初期化セグメントを最初に一度だけ送信する
+                // ディスコンティニュイティで EXT-X-MAP が切り替わることがあるため、URI が変わるたびに再送信する
+                if let Some(ref map) = pl.segments[next_to_send].map {
+                    if last_map_uri.as_deref() != Some(map.uri.as_str()) {
+                        let init_bytes = self.fetch_init_segment(map).await?;
+                        tx.send(init_bytes).await?;
+                        last_map_uri = Some(map.uri.clone());
+                    }
+                }
+
+                tx.send(data).await?;
+                next_to_send += 1;
+            }
+        }
+
+        // セグメントのダウンロードでエラーが発生した場合、残りのワーカーを中止し、
+        // FFmpeg プロセスを後始末してから最初のエラーを伝播する
+        if let Some(e) = first_err {
+            for w in workers {
+                w.abort();
+            }
+            drop(tx);
+            let _ = writer.await;
+            let _ = ffmpeg.kill().await;
+            return Err(e);
+        }
+
+        for w in workers {
+            w.await?;
+        }
+
+        // ===== ステップ 8: channel を閉じ、すべてのデータの書き込み完了を待機 =====
         drop(tx); // 送信側を閉じ、writer タスクにこれ以上のデータがないことを通知
         writer.await?;
 
-        // ===== ステップ 8: FFmpeg のパッケージング完了を待機 =====
+        // ===== ステップ 9: FFmpeg のパッケージング完了を待機 =====
         let status = ffmpeg.wait().await?;
         if !status.success() {
             return Err(Error::IoError(std::io::Error::new(
@@ -368,9 +832,70 @@ impl HLSDownloader {
             )));
         }
 
+        (self.progress)(Event::Completed);
+
         Ok(())
     }
 
+    /// `quality` 設定に従って Master Playlist からバリアントを1つ選択する
+    ///
+    /// - `Best`: 帯域幅が最大のバリアント
+    /// - `Worst`: 帯域幅が最小のバリアント
+    /// - `Height(h)`: 高さが `h` を超えない範囲で最も解像度が高いバリアント。
+    ///   該当するものがなければ、最も近い解像度のバリアントにフォールバックする
+    pub(crate) fn select_variant(
+        variants: &[m3u8_rs::VariantStream],
+        quality: Quality,
+    ) -> &m3u8_rs::VariantStream {
+        match quality {
+            Quality::Best => variants
+                .iter()
+                .max_by(|&a, &b| a.bandwidth.cmp(&b.bandwidth))
+                .expect("master playlist has no variants"),
+            Quality::Worst => variants
+                .iter()
+                .min_by(|&a, &b| a.bandwidth.cmp(&b.bandwidth))
+                .expect("master playlist has no variants"),
+            Quality::Height(target) => variants
+                .iter()
+                .filter(|v| v.resolution.map(|r| r.height <= target).unwrap_or(false))
+                .max_by_key(|v| v.resolution.map(|r| r.height).unwrap_or(0))
+                .or_else(|| {
+                    variants.iter().min_by_key(|v| {
+                        let height = v.resolution.map(|r| r.height).unwrap_or(0);
+                        (height as i64 - target as i64).abs()
+                    })
+                })
+                .expect("master playlist has no variants"),
+            Quality::AudioOnly => variants
+                .iter()
+                .filter(|v| Self::is_audio_only_variant(v))
+                .max_by(|&a, &b| a.bandwidth.cmp(&b.bandwidth))
+                .expect("master playlist has no audio-only variant"),
+        }
+    }
+
+    /// バリアントが映像トラックを持たない音声のみのレンディションかどうかを判定する
+    ///
+    /// `EXT-X-STREAM-INF` に解像度が無ければ音声のみとみなす。解像度が省略されることもあるため、
+    /// 解像度が無い場合でも `CODECS` に映像コーデックが含まれていれば音声のみとは判定しない
+    fn is_audio_only_variant(variant: &m3u8_rs::VariantStream) -> bool {
+        if variant.resolution.is_some() {
+            return false;
+        }
+        match variant.codecs.as_deref() {
+            Some(codecs) => !codecs.split(',').any(|c| {
+                let c = c.trim();
+                c.starts_with("avc1")
+                    || c.starts_with("hev1")
+                    || c.starts_with("hvc1")
+                    || c.starts_with("vp09")
+                    || c.starts_with("av01")
+            }),
+            None => true,
+        }
+    }
+
     /// プレイリストをダウンロード（Master Playlist と Media Playlist をサポート）
     ///
     /// URL が Master Playlist を指している場合、最高ビットレートのバリアントを選択して再帰的にダウンロード
@@ -380,11 +905,13 @@ impl HLSDownloader {
     /// - `url`: M3U8 プレイリスト URL
     /// - `output`: 出力ファイルパス
     /// - `ffmpeg_args`: FFmpeg の追加パラメータ
+    /// - `subtitles`: 埋め込む字幕トラックのリスト
     async fn download_playlist(
         &mut self,
         mut url: String,
         output: &Path,
         ffmpeg_args: Option<&[&str]>,
+        subtitles: &[SubtitleTrack],
     ) -> Result<(), Error> {
         loop {
             // プレイリストをダウンロードして解析
@@ -395,16 +922,13 @@ impl HLSDownloader {
             match pl {
                 // Media Playlist: すべてのセグメントを直接処理
                 m3u8_rs::Playlist::MediaPlaylist(pl) => {
-                    return self.parse_media_playlist(pl, output, ffmpeg_args).await;
+                    return self
+                        .parse_media_playlist(pl, output, ffmpeg_args, subtitles)
+                        .await;
                 }
-                // Master Playlist: 最高ビットレートのバリアントを選択して続行
+                // Master Playlist: `quality` 設定に従ってバリアントを選択して続行
                 m3u8_rs::Playlist::MasterPlaylist(mp) => {
-                    // 帯域幅が最も高いバリアントを選択（通常は最高画質を意味する）
-                    let target_pl = mp
-                        .variants
-                        .iter()
-                        .max_by(|&a, &b| a.bandwidth.cmp(&b.bandwidth))
-                        .unwrap();
+                    let target_pl = Self::select_variant(&mp.variants, self.quality);
 
                     // 選択したプレイリスト情報を記録
                     if let Some(res) = target_pl.resolution {
@@ -432,13 +956,20 @@ impl HLSDownloader {
     /// - `url`: M3U8 プレイリスト URL
     /// - `output`: 出力ファイルパス
     /// - `ffmpeg_args`: FFmpeg の追加パラメータ（メタデータ、添付ファイルなど）
+    /// - `subtitles`: 出力 MKV に埋め込む字幕トラック（URL・言語・コーデック）のリスト
     pub async fn download<P: AsRef<Path>>(
         &mut self,
         url: &str,
         output: P,
         ffmpeg_args: Option<&[&str]>,
+        subtitles: Option<&[SubtitleTrack]>,
     ) -> Result<(), Error> {
-        self.download_playlist(url.to_string(), output.as_ref(), ffmpeg_args)
-            .await
+        self.download_playlist(
+            url.to_string(),
+            output.as_ref(),
+            ffmpeg_args,
+            subtitles.unwrap_or(&[]),
+        )
+        .await
     }
 }
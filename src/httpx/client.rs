@@ -7,6 +7,7 @@ use backoff::ExponentialBackoff;
 use backoff::future::retry;
 use futures_util::TryStreamExt;
 use std::path::Path;
+use std::sync::RwLock;
 use thiserror::Error;
 use tokio::fs::OpenOptions;
 use tokio::io::AsyncWriteExt;
@@ -32,6 +33,9 @@ pub enum DownloadError {
 /// - ファイルダウンロード（レジューム機能をサポート）
 pub struct HttpXClient {
     inner: reqwest::Client,
+    /// ログイン済みセッションの `Cookie` ヘッダー値。`set_auth_cookie` で設定されると、以後の
+    /// すべてのリクエスト（ダウンロードを含む）に自動で付与される
+    auth_cookie: RwLock<Option<String>>,
 }
 
 /// ダウンロード進捗コールバック関数の型
@@ -51,7 +55,26 @@ impl HttpXClient {
             builder = builder.default_headers(h);
         }
         let client = builder.build()?;
-        Ok(HttpXClient { inner: client })
+        Ok(HttpXClient {
+            inner: client,
+            auth_cookie: RwLock::new(None),
+        })
+    }
+
+    /// ログイン済みセッションの `Cookie` ヘッダーを設定する
+    ///
+    /// 設定後は `get_with_retry`/`post_with_retry`/`download` のすべてのリクエストに自動で付与され、
+    /// 会員限定・有料限定コンテンツへのアクセスに使われる
+    pub fn set_auth_cookie(&self, cookie: impl Into<String>) {
+        *self.auth_cookie.write().unwrap() = Some(cookie.into());
+    }
+
+    /// 設定されていれば `Cookie` ヘッダーをリクエストビルダーに付与する
+    fn with_auth_cookie(&self, builder: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.auth_cookie.read().unwrap().as_deref() {
+            Some(cookie) => builder.header(reqwest::header::COOKIE, cookie),
+            None => builder,
+        }
     }
 
     /// nicochannel.jp API に必要なデフォルトヘッダーを追加
@@ -93,7 +116,7 @@ impl HttpXClient {
     ) -> Result<reqwest::Response, reqwest::Error> {
         retry(ExponentialBackoff::default(), {
             move || {
-                let mut builder = self.inner.get(url);
+                let mut builder = self.with_auth_cookie(self.inner.get(url));
                 if let Some(f) = builder_fn {
                     builder = f(builder)
                 }
@@ -110,7 +133,7 @@ impl HttpXClient {
     ) -> Result<reqwest::Response, reqwest::Error> {
         retry(ExponentialBackoff::default(), {
             move || {
-                let mut builder = self.inner.post(url);
+                let mut builder = self.with_auth_cookie(self.inner.post(url));
                 if let Some(f) = builder_fn {
                     builder = f(builder)
                 }
@@ -142,7 +165,7 @@ impl HttpXClient {
         }
 
         // ===== 2. HTTP リクエストを構築 =====
-        let mut builder = self.inner.get(url);
+        let mut builder = self.with_auth_cookie(self.inner.get(url));
         if let Some(f) = builder_fn {
             builder = f(builder);
         }
@@ -217,6 +240,9 @@ impl HttpXClient {
 
 impl From<reqwest::Client> for HttpXClient {
     fn from(client: reqwest::Client) -> Self {
-        HttpXClient { inner: client }
+        HttpXClient {
+            inner: client,
+            auth_cookie: RwLock::new(None),
+        }
     }
 }
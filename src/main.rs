@@ -5,6 +5,8 @@
 
 use clap::Parser;
 use log::{error, info};
+use ncd::hls::Quality;
+use ncd::nicochannel::archive::DownloadArchive;
 use ncd::nicochannel::client::{NicoChannelClient, NicoChannelError};
 use regex::Regex;
 use std::path::PathBuf;
@@ -29,10 +31,61 @@ struct Args {
     #[arg(long)]
     no_persistence_db: bool,
 
+    /// HLS セグメントの同時ダウンロード数
+    #[arg(short = 'j', long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// 画質選択: "best"（デフォルト）、"worst"、"audio"（音声のみ）、
+    /// または "720p"/"1080p" のような解像度指定
+    #[arg(long, default_value = "best")]
+    quality: Quality,
+
+    /// ダウンロード対象の画質を上書きする（"best"/"worst"/"audio"/"720p" など）。
+    /// `--quality` と異なり、HLS Master Playlist から該当するバリアントを解決してからダウンロードする
+    #[arg(long)]
+    format: Option<Quality>,
+
+    /// 各 URL について選択可能な画質バリアントを一覧表示し、ダウンロードは行わない
+    #[arg(long)]
+    list_formats: bool,
+
+    /// ダウンロードを行わず、各動画の正規化されたメタデータを1行1 JSON で標準出力に出力する
+    #[arg(long)]
+    dump_json: bool,
+
+    /// `--dump-json` のエイリアス（yt-dlp の `--simulate` に相当）
+    #[arg(long)]
+    simulate: bool,
+
+    /// ダウンロード済みの content_code を記録するアーカイブファイル。指定すると、記録済みの動画は
+    /// スキップし、成功したダウンロードを新たに記録する（yt-dlp の `--download-archive` 相当）
+    #[arg(long)]
+    download_archive: Option<PathBuf>,
+
+    /// ログイン済みブラウザセッションの Cookie ヘッダー値。会員限定・有料限定コンテンツの
+    /// ダウンロードに必要（`--email`/`--password` の代わりに指定可能）
+    #[arg(long)]
+    session_token: Option<String>,
+
+    /// ログイン用メールアドレス（`--password` と併用）
+    #[arg(long, requires = "password")]
+    email: Option<String>,
+
+    /// ログイン用パスワード（`--email` と併用）
+    #[arg(long, requires = "email")]
+    password: Option<String>,
+
     /// ダウンロードする URL のリスト
     urls: Vec<String>,
 }
 
+impl Args {
+    /// ダウンロードを行わずメタデータのみを出力するモードかどうか
+    fn dump_only(&self) -> bool {
+        self.dump_json || self.simulate
+    }
+}
+
 lazy_static::lazy_static! {
     // グローバル状態：ダウンロード中のファイルを追跡
     static ref DOWNLOADING_FILES: Arc<Mutex<Vec<PathBuf>>> = Arc::new(Mutex::new(Vec::new()));
@@ -42,20 +95,15 @@ async fn download(
     nc: &mut NicoChannelClient,
     args: &Args,
     vid: &str,
+    archive: Option<&DownloadArchive>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // ===== 単一の動画をダウンロード =====
     // 出力ファイルパスを取得して登録
     let output_file = {
         let video_info = nc.video_info(vid).await?;
-        let title = video_info["title"]
-            .as_str()
-            .ok_or("Failed to get title from video info")?;
-        let content_code = video_info["content_code"]
-            .as_str()
-            .ok_or("Failed to get content_code from video info")?;
         args.output_dir.join(format!(
             "{}.mkv",
-            NicoChannelClient::output_filename(title, content_code)
+            NicoChannelClient::output_filename(&video_info.title, &video_info.content_code)
         ))
     };
 
@@ -66,7 +114,9 @@ async fn download(
     }
 
     // ダウンロード実行
-    let result = nc.download_video(vid, &args.output_dir).await;
+    let result = nc
+        .download_video(vid, &args.output_dir, args.format, archive)
+        .await;
 
     // ファイルパスを登録から削除
     {
@@ -121,27 +171,29 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         std::process::exit(130); // SIGINT の標準的な終了コード
     });
 
-    // ffmpeg バイナリが存在するか確認
-    let ffmpeg_check = if cfg!(target_os = "windows") {
-        Command::new("where").arg("ffmpeg").output()
-    } else {
-        Command::new("which").arg("ffmpeg").output()
-    };
+    let args = Args::parse();
 
-    match ffmpeg_check {
-        Ok(output) if output.status.success() => {
-            // ffmpeg が見つかった
-        }
-        _ => {
-            eprintln!("Error: ffmpeg binary not found.");
-            eprintln!("Please install ffmpeg and ensure it is in your PATH.");
-            eprintln!("Visit https://ffmpeg.org/download.html for installation instructions.");
-            std::process::exit(1);
+    // ffmpeg バイナリが存在するか確認（`--dump-json`/`--simulate` はダウンロードを行わないため不要）
+    if !args.dump_only() {
+        let ffmpeg_check = if cfg!(target_os = "windows") {
+            Command::new("where").arg("ffmpeg").output()
+        } else {
+            Command::new("which").arg("ffmpeg").output()
+        };
+
+        match ffmpeg_check {
+            Ok(output) if output.status.success() => {
+                // ffmpeg が見つかった
+            }
+            _ => {
+                eprintln!("Error: ffmpeg binary not found.");
+                eprintln!("Please install ffmpeg and ensure it is in your PATH.");
+                eprintln!("Visit https://ffmpeg.org/download.html for installation instructions.");
+                std::process::exit(1);
+            }
         }
     }
 
-    let args = Args::parse();
-
     // 少なくとも1つの URL が提供されているか確認
     if args.urls.is_empty() {
         eprintln!("Error: at least one URL is required");
@@ -158,6 +210,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         r"https?://nicochannel.jp/(?P<channel_name>[^/]*)/?(?:video/(?P<video_id>\w*))?",
     )?;
 
+    // アーカイブファイルが指定されていれば読み込む（複数 URL 間で共有する）
+    let archive = match &args.download_archive {
+        Some(path) => Some(DownloadArchive::load(path).await?),
+        None => None,
+    };
+
     // 各 URL を処理
     for url in &args.urls {
         // URL からチャンネル名と動画 ID を抽出
@@ -168,24 +226,42 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .as_str();
         let video_id = caps.name("video_id").map(|m| m.as_str());
 
-        // クライアントを作成し、チャンネル ID を読み込む
-        let mut client = NicoChannelClient::new();
+        // クライアントを作成し、必要であればログインしてからチャンネル ID を読み込む
+        let mut client = NicoChannelClient::with_options(args.concurrency, args.quality);
+        if let Some(ref token) = args.session_token {
+            client.login_with_session_token(token.clone());
+        } else if let (Some(ref email), Some(ref password)) = (&args.email, &args.password) {
+            client.login(email, password).await?;
+        }
         let channel_id = client.load_channel_id(channel_name).await?;
 
         if let Some(vid) = video_id {
-            match download(&mut client, &args, vid).await {
+            if args.list_formats {
+                for format in client.list_formats(vid).await? {
+                    println!("{}", serde_json::to_string(&format)?);
+                }
+                continue;
+            }
+
+            if args.dump_only() {
+                client.dump_json(&args.output_dir, vid).await?;
+                continue;
+            }
+
+            match download(&mut client, &args, vid, archive.as_ref()).await {
                 Ok(_) => info!("Successfully downloaded video {}", vid),
-                Err(e) => {
-                    if let Some(_) = e.downcast_ref::<NicoChannelError>() {
-                        if args.incremental {
-                            // ファイルが既に存在する場合の処理
-                            info!("File exists, stopping (incremental mode)");
-                            return Ok(());
-                        }
-                    } else {
-                        error!("Error downloading video {}: {}", vid, e);
+                Err(e) => match e.downcast_ref::<NicoChannelError>() {
+                    Some(NicoChannelError::NCDFileExist) if args.incremental => {
+                        // ファイルが既に存在する場合の処理
+                        info!("File exists, stopping (incremental mode)");
+                        return Ok(());
                     }
-                }
+                    Some(NicoChannelError::NCDFileExist) => {}
+                    Some(NicoChannelError::MembershipRequired(_)) => {
+                        error!("Video {} requires a membership or purchase: {}", vid, e);
+                    }
+                    None => error!("Error downloading video {}: {}", vid, e),
+                },
             }
         } else {
             // ===== チャンネルのすべての動画をダウンロード =====
@@ -193,38 +269,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             for video in videos {
                 // 動画タイプのみを処理（video_media_type.id == 1）
-                if video["video_media_type"]["id"].as_i64() != Some(1) {
+                if video.video_media_type.as_ref().map(|t| t.id) != Some(1) {
                     continue;
                 }
 
                 // 動画の権限を確認
                 // delivery_target_id: 1=会員限定, 3=有料限定
                 // 限定動画で無料期間がない場合はスキップ
-                let delivery_target_id = video["video_delivery_target"]["id"].as_i64();
+                let delivery_target_id = video.video_delivery_target.as_ref().map(|t| t.id);
                 if delivery_target_id == Some(1) || delivery_target_id == Some(3) {
-                    if video["video_free_periods"].is_null() {
+                    if video.video_free_periods.is_none() {
                         continue;
                     }
                 }
 
                 // 動画情報を抽出
-                let content_code = video["content_code"]
-                    .as_str()
-                    .ok_or("Failed to get content_code from video")?;
+                let content_code = video.content_code.as_str();
+
+                if args.dump_only() {
+                    client.dump_json(&args.output_dir, content_code).await?;
+                    continue;
+                }
 
-                match download(&mut client, &args, content_code).await {
+                match download(&mut client, &args, content_code, archive.as_ref()).await {
                     Ok(_) => info!("Successfully downloaded video {}", content_code),
-                    Err(e) => {
+                    Err(e) => match e.downcast_ref::<NicoChannelError>() {
                         // ファイルが既に存在する場合の処理
-                        if let Some(_) = e.downcast_ref::<NicoChannelError>() {
-                            if args.incremental {
-                                info!("File exists, stopping (incremental mode)");
-                                return Ok(());
-                            }
-                        } else {
-                            error!("Error downloading video {}: {}", content_code, e);
+                        Some(NicoChannelError::NCDFileExist) if args.incremental => {
+                            info!("File exists, stopping (incremental mode)");
+                            return Ok(());
                         }
-                    }
+                        Some(NicoChannelError::NCDFileExist) => {}
+                        Some(NicoChannelError::MembershipRequired(_)) => {
+                            error!(
+                                "Video {} requires a membership or purchase: {}",
+                                content_code, e
+                            );
+                        }
+                        None => error!("Error downloading video {}: {}", content_code, e),
+                    },
                 }
             }
         }
@@ -0,0 +1,65 @@
+//! ダウンロード済み動画の永続アーカイブ（yt-dlp の `--download-archive` 相当）
+//!
+//! 改行区切りの `content_code` を記録したテキストファイルとして永続化する。出力ファイルの
+//! 存在チェック（`NicoChannelError::NCDFileExist`）と異なり、ファイルのリネームや移動の
+//! 影響を受けない
+
+use std::collections::HashSet;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// ダウンロード済みの `content_code` を記録する永続アーカイブ
+///
+/// 複数タスクから並行して `record` が呼ばれても追記が競合しないよう、内部の `Mutex` で直列化する
+pub struct DownloadArchive {
+    path: PathBuf,
+    recorded: Mutex<HashSet<String>>,
+}
+
+impl DownloadArchive {
+    /// アーカイブファイルを読み込む
+    ///
+    /// ファイルが存在しない場合は空のアーカイブとして扱う（初回実行時に新規作成されるため）
+    pub async fn load(path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let path = path.into();
+        let recorded = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents
+                .lines()
+                .map(|line| line.trim().to_string())
+                .filter(|line| !line.is_empty())
+                .collect(),
+            Err(e) if e.kind() == ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path,
+            recorded: Mutex::new(recorded),
+        })
+    }
+
+    /// `content_code` が既に記録済みかどうかを確認する
+    pub async fn contains(&self, content_code: &str) -> bool {
+        self.recorded.lock().await.contains(content_code)
+    }
+
+    /// `content_code` を記録し、アーカイブファイルへ追記する
+    ///
+    /// 既に記録済みの場合は何もしない（ファイルへの重複書き込みも行わない）
+    pub async fn record(&self, content_code: &str) -> std::io::Result<()> {
+        let mut recorded = self.recorded.lock().await;
+        if !recorded.insert(content_code.to_string()) {
+            return Ok(());
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(format!("{}\n", content_code).as_bytes())
+            .await
+    }
+}
@@ -6,33 +6,30 @@
 //! - HLS ストリーム URL の取得
 //! - 動画のダウンロードとメタデータ・サムネイルの埋め込み
 
-use crate::hls::HLSDownloader;
+use crate::dash::DASHDownloader;
+use crate::hls::{HLSDownloader, Quality, SubtitleCodec, SubtitleTrack};
 use crate::httpx::HttpXClient;
+use crate::nicochannel::archive::DownloadArchive;
+use crate::nicochannel::danmaku::{self, DanmakuOptions};
+use crate::nicochannel::model::{ChannelInfo, Comment, VideoPage};
 use crate::utils::FileNameUtils;
+use futures_util::stream::{self, Stream, StreamExt};
 use lazy_static::lazy_static;
 use log;
-use std::collections::{HashMap, HashSet};
+use m3u8_rs;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 
 /// NicoChannel API ベース URL
 const PREFIX: &str = "https://api.nicochannel.jp";
 
-lazy_static! {
-    static ref METADATA_KEY: HashSet<&'static str> = {
-        HashSet::from([
-            "content_code",      // コンテンツコード
-            "display_date",      // 表示日付
-            "description",       // 説明
-            "live_finished_at",  // 配信終了時刻
-            "released_at",       // 公開時刻
-            "closed_at",         // 閉鎖時刻
-            "title",             // タイトル
-        ])
-    };
-}
+/// `download_comments` が取得するコメント数のデフォルト上限（yt-dlp の niconicochannelplus 抽出器に合わせる）
+const DEFAULT_MAX_COMMENTS: usize = 120;
 
 lazy_static! {
     static ref DEFAULT_HEADERS: reqwest::header::HeaderMap = {
@@ -45,12 +42,119 @@ lazy_static! {
     };
 }
 
+/// 再生セッションのキープアライブを送り続けるバックグラウンドタスクのガード
+///
+/// ニコニコ DMC プロトコルと同様、再生セッションには生存期間があり、長時間のダウンロード中に
+/// 切れてしまうと配信元がセッションを失効させる。このガードが保持されている間、バックグラウンド
+/// タスクが定期的にキープアライブを送信し続け、drop されると同時にタスクを中断する
+pub struct HeartbeatGuard {
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for HeartbeatGuard {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
 /// NicoChannel クライアントエラータイプ
 #[derive(Error, Debug)]
 pub enum NicoChannelError {
     /// ファイルが既に存在
     #[error("NCD file exist")]
     NCDFileExist,
+
+    /// 会員限定・有料限定コンテンツで、現在のセッションが視聴権限を持っていない
+    #[error("this content requires an active membership or purchase ({0})")]
+    MembershipRequired(String),
+}
+
+/// レスポンスのステータスを確認し、`403 Forbidden` を会員権限不足として区別する
+///
+/// 匿名セッションの API は、会員限定・有料限定コンテンツに対して 403 を返す。それ以外のエラー
+/// ステータスは通常どおり `reqwest::Error` として扱う
+fn check_membership_status(
+    resp: reqwest::Response,
+    context: &str,
+) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+    if resp.status() == reqwest::StatusCode::FORBIDDEN {
+        return Err(Box::new(NicoChannelError::MembershipRequired(
+            context.to_string(),
+        )));
+    }
+    Ok(resp.error_for_status()?)
+}
+
+/// `display_date`/`released_at` の解析に使う日時フォーマット
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// レスポンスにセッション生存期間が含まれない場合のフォールバック値（秒）
+const DEFAULT_SESSION_LIFETIME_SECS: u64 = 120;
+
+/// ハートビートの送信間隔を、セッション生存期間に対してどれだけ早めるかの係数
+///
+/// 生存期間ぎりぎりで送ると通信の遅延やリトライで間に合わない恐れがあるため、余裕を持たせる
+const HEARTBEAT_INTERVAL_RATIO: f64 = 0.5;
+
+/// アーカイブ済み動画とライブ/配信予定コンテンツを区別するフィルタ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    /// 配信が終了し、アーカイブとして視聴できる動画（`live_finished_at` が設定されている）
+    Archived,
+    /// ライブ配信中または配信予定のコンテンツ（`live_finished_at` が未設定）
+    LiveOrUpcoming,
+}
+
+/// [`NicoChannelClient::video_pages_stream`] の絞り込み条件
+#[derive(Debug, Clone, Default)]
+pub struct VideoPageFilter {
+    /// この日時以降の動画のみを対象にする（`display_date`、無ければ `released_at` で判定）
+    pub after: Option<chrono::NaiveDateTime>,
+    /// この日時以前の動画のみを対象にする（`display_date`、無ければ `released_at` で判定）
+    pub before: Option<chrono::NaiveDateTime>,
+    /// コンテンツ種別による絞り込み
+    pub content_type: Option<ContentType>,
+}
+
+impl VideoPageFilter {
+    /// 動画の日時（`display_date` を優先し、無ければ `released_at`）を解析する
+    fn video_datetime(video: &VideoPage) -> Option<chrono::NaiveDateTime> {
+        video
+            .display_date
+            .as_deref()
+            .or(video.released_at.as_deref())
+            .and_then(|s| chrono::NaiveDateTime::parse_from_str(s, DATE_FORMAT).ok())
+    }
+
+    /// この動画がフィルタ条件に一致するか判定する
+    fn matches(&self, video: &VideoPage) -> bool {
+        if let Some(content_type) = self.content_type {
+            let is_archived = video.live_finished_at.is_some();
+            let wants_archived = content_type == ContentType::Archived;
+            if is_archived != wants_archived {
+                return false;
+            }
+        }
+
+        if self.after.is_some() || self.before.is_some() {
+            let Some(dt) = Self::video_datetime(video) else {
+                // 日付が判定できない動画は、日付フィルタが指定されている場合は対象外とする
+                return false;
+            };
+            if let Some(after) = self.after {
+                if dt < after {
+                    return false;
+                }
+            }
+            if let Some(before) = self.before {
+                if dt > before {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
 }
 
 /// NicoChannel API クライアント
@@ -64,18 +168,45 @@ pub struct NicoChannelClient {
     hc: Arc<HttpXClient>,
     /// HLS ダウンローダー
     hls_downloader: HLSDownloader,
+    /// DASH ダウンローダー（`authenticated_url` が MPD マニフェストを指している場合のフォールバック）
+    dash_downloader: DASHDownloader,
     /// 動画情報キャッシュ（重複リクエストを回避）
-    cache: HashMap<String, Arc<serde_json::Value>>,
+    cache: HashMap<String, Arc<VideoPage>>,
     /// 現在のチャンネル ID（fc_site_id ヘッダーの設定に使用）
     channel_id: Option<i64>,
 }
 impl NicoChannelClient {
-    /// 新しい NicoChannel クライアントを作成
+    /// 新しい NicoChannel クライアントを作成（セグメント同時ダウンロード数はデフォルト値を使用）
     pub fn new() -> Self {
         let hc = Arc::new(HttpXClient::new(Some(DEFAULT_HEADERS.clone())).unwrap());
         Self {
             hc: hc.clone(),
             hls_downloader: HLSDownloader::new(hc.clone()),
+            dash_downloader: DASHDownloader::new(hc.clone()),
+            cache: HashMap::new(),
+            channel_id: None,
+        }
+    }
+
+    /// セグメント同時ダウンロード数を指定して NicoChannel クライアントを作成（画質はデフォルトの `Best`）
+    ///
+    /// # 引数
+    /// - `concurrency`: HLS セグメントの同時ダウンロード数の上限
+    pub fn with_concurrency(concurrency: usize) -> Self {
+        Self::with_options(concurrency, Quality::default())
+    }
+
+    /// セグメント同時ダウンロード数と画質選択を指定して NicoChannel クライアントを作成
+    ///
+    /// # 引数
+    /// - `concurrency`: HLS セグメントの同時ダウンロード数の上限
+    /// - `quality`: Master Playlist から選択するバリアントの画質
+    pub fn with_options(concurrency: usize, quality: Quality) -> Self {
+        let hc = Arc::new(HttpXClient::new(Some(DEFAULT_HEADERS.clone())).unwrap());
+        Self {
+            hc: hc.clone(),
+            hls_downloader: HLSDownloader::with_options(hc.clone(), concurrency, quality),
+            dash_downloader: DASHDownloader::new(hc.clone()),
             cache: HashMap::new(),
             channel_id: None,
         }
@@ -86,6 +217,66 @@ impl NicoChannelClient {
         self.channel_id.map(|id| id.to_string())
     }
 
+    /// `authenticated_url` が HLS (m3u8) ではなく DASH (MPD) マニフェストを指しているか判定する
+    fn is_dash_manifest_url(url: &str) -> bool {
+        url.split(['?', '#'])
+            .next()
+            .unwrap_or(url)
+            .to_ascii_lowercase()
+            .ends_with(".mpd")
+    }
+
+    /// 既に取得済みのセッション Cookie を使ってログイン状態にする
+    ///
+    /// ブラウザでログイン後に送信される `Cookie` ヘッダーの値（例：`_session_id=...`）をそのまま渡す。
+    /// 以後のすべてのリクエストにこの Cookie が付与され、会員限定・有料限定コンテンツへアクセスできる
+    ///
+    /// # 引数
+    /// - `cookie`: ログイン済みセッションの `Cookie` ヘッダー値
+    pub fn login_with_session_token(&self, cookie: impl Into<String>) {
+        self.hc.set_auth_cookie(cookie);
+    }
+
+    /// メールアドレスとパスワードでログインし、取得したセッション Cookie を以後のリクエストに使う
+    ///
+    /// # 引数
+    /// - `email`: ログイン用メールアドレス
+    /// - `password`: パスワード
+    pub async fn login(
+        &self,
+        email: &str,
+        password: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let url = format!("{}/fc/sessions/sign_in", PREFIX);
+        let resp = self
+            .hc
+            .post_with_retry(
+                &url,
+                Some(&move |b| b.json(&serde_json::json!({ "email": email, "password": password }))),
+            )
+            .await?
+            .error_for_status()?;
+
+        // `Set-Cookie` は複数回送られることがあり、各値には `Cookie` ヘッダーに含めてはいけない
+        // 属性（`Path`/`HttpOnly`/`SameSite` など）が付与されている。すべての値から
+        // `name=value` の部分だけを取り出し、`; ` で連結して `Cookie` ヘッダーとして送り返す
+        let cookie = resp
+            .headers()
+            .get_all(reqwest::header::SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .map(|v| v.split(';').next().unwrap_or(v).trim().to_string())
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        if cookie.is_empty() {
+            return Err("Login response did not contain a session cookie".into());
+        }
+
+        self.hc.set_auth_cookie(cookie);
+        Ok(())
+    }
+
     /// チャンネル名に基づいてチャンネル ID を読み込む
     ///
     /// # 引数
@@ -113,21 +304,17 @@ impl NicoChannelClient {
             .await?
             .error_for_status()?;
         let body: serde_json::Value = resp.json().await?;
-        let channel_id = body["data"]["content_providers"]["id"]
-            .as_i64()
-            .ok_or_else(|| {
+        let channel: ChannelInfo = serde_json::from_value(body["data"]["content_providers"].clone())
+            .map_err(|e| {
                 format!(
-                    "Failed to get channel_id from response: {}",
-                    serde_json::to_string_pretty(&body).unwrap_or_default()
+                    "Failed to get channel_id from response: {} ({})",
+                    serde_json::to_string_pretty(&body).unwrap_or_default(),
+                    e
                 )
-            })
-            .map_err(|e| {
-                Box::new(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
-                    as Box<dyn std::error::Error>
             })?;
 
-        self.channel_id = Some(channel_id);
-        Ok(channel_id)
+        self.channel_id = Some(channel.id);
+        Ok(channel.id)
     }
 
     #[allow(dead_code)]
@@ -151,17 +338,178 @@ impl NicoChannelClient {
     /// - `channel_id`: チャンネル ID
     ///
     /// # 戻り値
-    /// すべての動画の JSON データリスト（ページネーションは自動処理済み）
+    /// すべての動画ページのリスト（ページネーションは自動処理済み）
+    ///
+    /// チャンネルが大きい場合、一気に全ページをバッファしたくなければ
+    /// 代わりに [`Self::video_pages_stream`] を使うこと
     pub async fn video_pages(
         &self,
         channel_id: i64,
-    ) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    ) -> Result<Vec<VideoPage>, Box<dyn std::error::Error>> {
         let mut page = 1;
-        let mut videos: Vec<serde_json::Value> = Vec::new();
-        let url = format!("{}/fc/fanclub_sites/{}/video_pages", PREFIX, channel_id);
+        let mut videos: Vec<VideoPage> = Vec::new();
 
-        // すべてのページをループで取得
         loop {
+            let list = self.fetch_video_pages_page(channel_id, page).await?;
+            if list.is_empty() {
+                break;
+            }
+            videos.extend(list);
+            page += 1;
+        }
+        Ok(videos)
+    }
+
+    /// 動画一覧の1ページ分を取得する
+    ///
+    /// `page` は 1 始まり。空の `Vec` はそれ以上ページがないことを意味する
+    async fn fetch_video_pages_page(
+        &self,
+        channel_id: i64,
+        page: usize,
+    ) -> Result<Vec<VideoPage>, Box<dyn std::error::Error>> {
+        Self::fetch_video_pages_page_with(&self.hc, self.fc_site_id().as_deref(), channel_id, page)
+            .await
+    }
+
+    /// [`Self::fetch_video_pages_page`] の実体。`&self` を借用し続けないよう、必要な
+    /// `hc`/`fc_site_id` だけを受け取る形にしてある（[`Self::video_pages_stream`] が
+    /// クライアント本体を借用せずにページ取得できるようにするため）
+    async fn fetch_video_pages_page_with(
+        hc: &HttpXClient,
+        fc_site_id: Option<&str>,
+        channel_id: i64,
+        page: usize,
+    ) -> Result<Vec<VideoPage>, Box<dyn std::error::Error>> {
+        let url = format!("{}/fc/fanclub_sites/{}/video_pages", PREFIX, channel_id);
+        let page_str = page.to_string();
+        let fc_site_id = fc_site_id.unwrap_or("1").to_string();
+        let resp = hc
+            .get_with_retry(
+                &url,
+                Some(&move |b| {
+                    b.query(&[
+                        ("page", page_str.as_str()),
+                        ("per_page", "24"),        // 1ページあたり24個の動画
+                        ("sort", "-display_date"), // 表示日付の降順でソート
+                    ])
+                    .header("Fc_site_id", fc_site_id.as_str())
+                }),
+            )
+            .await?
+            .error_for_status()?;
+
+        let data: serde_json::Value = resp.json().await?;
+        let list = match data["data"]["video_pages"]["list"].as_array() {
+            Some(list) => list,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut videos = Vec::with_capacity(list.len());
+        for item in list {
+            videos.push(serde_json::from_value(item.clone())?);
+        }
+        Ok(videos)
+    }
+
+    /// チャンネルの動画ページを、必要になったページだけ取得する遅延ストリームとして返す
+    ///
+    /// yt-dlp の `OnDemandPagedList` と同様、消費者が前のページの要素を読み切るまで
+    /// 次のページはリクエストされない。`filter` と組み合わせれば、カタログ全体を
+    /// バッファすることなく「日付 X 以降にアップロードされたものすべて」のような
+    /// ダウンロードが可能になる
+    ///
+    /// クライアント本体を借用し続けない（`hc`/`fc_site_id` だけを借用時にクローンする）ため、
+    /// 返されたストリームを消費しながら同じクライアントの `&mut self` メソッド
+    /// （[`Self::download_video`] など）を呼び出せる
+    ///
+    /// # 引数
+    /// - `channel_id`: チャンネル ID
+    /// - `filter`: 日付範囲・コンテンツ種別による絞り込み条件
+    pub fn video_pages_stream(
+        &self,
+        channel_id: i64,
+        filter: VideoPageFilter,
+    ) -> impl Stream<Item = Result<VideoPage, Box<dyn std::error::Error>>> + 'static {
+        struct State {
+            hc: Arc<HttpXClient>,
+            fc_site_id: Option<String>,
+            channel_id: i64,
+            page: usize,
+            buffer: std::vec::IntoIter<VideoPage>,
+            exhausted: bool,
+            filter: VideoPageFilter,
+        }
+
+        let initial = State {
+            hc: self.hc.clone(),
+            fc_site_id: self.fc_site_id(),
+            channel_id,
+            page: 1,
+            buffer: Vec::new().into_iter(),
+            exhausted: false,
+            filter,
+        };
+
+        stream::unfold(initial, |mut state| async move {
+            loop {
+                if let Some(video) = state.buffer.next() {
+                    if state.filter.matches(&video) {
+                        return Some((Ok(video), state));
+                    }
+                    continue;
+                }
+
+                if state.exhausted {
+                    return None;
+                }
+
+                match Self::fetch_video_pages_page_with(
+                    &state.hc,
+                    state.fc_site_id.as_deref(),
+                    state.channel_id,
+                    state.page,
+                )
+                .await
+                {
+                    Ok(list) => {
+                        state.page += 1;
+                        if list.is_empty() {
+                            state.exhausted = true;
+                            continue;
+                        }
+                        state.buffer = list.into_iter();
+                    }
+                    Err(e) => {
+                        state.exhausted = true;
+                        return Some((Err(e), state));
+                    }
+                }
+            }
+        })
+    }
+
+    /// 動画のコメント（視聴者コメント）を取得（ページネーション対応）
+    ///
+    /// yt-dlp の niconicochannelplus 抽出器の `max_comments`（デフォルト 120）に合わせ、
+    /// 上限に達するかページが尽きるまでコメント取得用エンドポイントをページングする
+    ///
+    /// # 引数
+    /// - `video_id`: 動画 ID
+    /// - `max_comments`: 取得するコメント数の上限
+    ///
+    /// # 戻り値
+    /// 再生位置の昇順とは限らないコメントのリスト（上限件数まで）
+    pub async fn download_comments(
+        &self,
+        video_id: &str,
+        max_comments: usize,
+    ) -> Result<Vec<Comment>, Box<dyn std::error::Error>> {
+        let mut page = 1;
+        let mut comments: Vec<Comment> = Vec::new();
+        let url = format!("{}/fc/video_pages/{}/comments", PREFIX, video_id);
+
+        while comments.len() < max_comments {
             let page_str = page.to_string();
             let fc_site_id = self.fc_site_id().unwrap_or("1".to_string());
             let resp = self
@@ -171,8 +519,7 @@ impl NicoChannelClient {
                     Some(&move |b| {
                         b.query(&[
                             ("page", page_str.as_str()),
-                            ("per_page", "24"),        // 1ページあたり24個の動画
-                            ("sort", "-display_date"), // 表示日付の降順でソート
+                            ("per_page", "100"), // 1ページあたり100件のコメント
                         ])
                         .header("Fc_site_id", fc_site_id.as_str())
                     }),
@@ -181,18 +528,21 @@ impl NicoChannelClient {
                 .error_for_status()?;
 
             let data: serde_json::Value = resp.json().await?;
-            let list = data["data"]["video_pages"]["list"].as_array();
+            let list = data["data"]["comments"]["list"].as_array();
 
             // 現在のページにデータがない場合、すべてのページを取得完了
             if list.is_none() || list.unwrap().is_empty() {
                 break;
             }
 
-            let list: Vec<serde_json::Value> = list.unwrap().to_vec();
-            videos.extend(list);
+            for item in list.unwrap() {
+                comments.push(serde_json::from_value(item.clone())?);
+            }
             page += 1;
         }
-        Ok(videos)
+
+        comments.truncate(max_comments);
+        Ok(comments)
     }
 
     /// 出力ファイル名を生成
@@ -221,7 +571,7 @@ impl NicoChannelClient {
     pub async fn video_info(
         &mut self,
         video_id: &str,
-    ) -> Result<Arc<serde_json::Value>, Box<dyn std::error::Error>> {
+    ) -> Result<Arc<VideoPage>, Box<dyn std::error::Error>> {
         if let Some(cache) = self.cache.get(video_id) {
             return Ok(cache.clone());
         }
@@ -233,27 +583,31 @@ impl NicoChannelClient {
                 &url,
                 Some(&move |b| b.header("Fc_site_id", fc_site_id.as_str())),
             )
-            .await?
-            .error_for_status()?;
+            .await?;
+        let resp = check_membership_status(resp, video_id)?;
         let body: serde_json::Value = resp.json().await?;
-        let info = Arc::new(body["data"]["video_page"].clone());
+        let info: VideoPage = serde_json::from_value(body["data"]["video_page"].clone())?;
+        let info = Arc::new(info);
         self.cache.insert(video_id.to_string(), info.clone());
         Ok(info)
     }
 
     /// 動画の HLS ストリーム URL を取得
     ///
-    /// まず session_id を取得し、その後 authenticated_url 内のプレースホルダーを置換する必要がある
+    /// まず session_id を取得し、その後 authenticated_url 内のプレースホルダーを置換する必要がある。
+    /// あわせてセッションのキープアライブを送り続けるバックグラウンドタスクを起動し、その
+    /// [`HeartbeatGuard`] を返す。呼び出し側はセッションを使い終えるまでガードを保持すること
+    /// （drop されるとハートビートは停止し、セッションはやがて失効する）
     ///
     /// # 引数
     /// - `video_id`: 動画 ID
     ///
     /// # 戻り値
-    /// 完全な HLS M3U8 URL
+    /// 完全な HLS M3U8 URL と、セッションのキープアライブを維持するガード
     pub async fn get_video_hls_url(
         &mut self,
         video_id: &str,
-    ) -> Result<String, Box<dyn std::error::Error>> {
+    ) -> Result<(String, HeartbeatGuard), Box<dyn std::error::Error>> {
         // 動画情報を取得（authenticated_url テンプレートを含む）
         let info = self.video_info(video_id).await?;
 
@@ -269,57 +623,239 @@ impl NicoChannelClient {
                         .header("Fc_site_id", fc_site_id.as_str())
                 }),
             )
-            .await?
-            .error_for_status()?;
+            .await?;
+        let resp = check_membership_status(resp, video_id)?;
         let body: serde_json::Value = resp.json().await?;
         let session_id = body["data"]["session_id"]
             .as_str()
-            .ok_or("Failed to get session_id from response")?;
+            .ok_or("Failed to get session_id from response")?
+            .to_string();
+        let lifetime_secs = body["data"]["lifetime"]
+            .as_u64()
+            .unwrap_or(DEFAULT_SESSION_LIFETIME_SECS);
 
         // URL テンプレート内の session_id プレースホルダーを置換
-        let auth_url = info["video_stream"]["authenticated_url"]
-            .as_str()
-            .ok_or("Failed to get authenticated_url from video info")?;
-        Ok(auth_url.replace("{session_id}", session_id))
+        let auth_url = info
+            .video_stream
+            .as_ref()
+            .ok_or("Failed to get authenticated_url from video info")?
+            .authenticated_url
+            .as_str();
+        let hls_url = auth_url.replace("{session_id}", &session_id);
+
+        let heartbeat = self.spawn_session_heartbeat(video_id, session_id, lifetime_secs);
+        Ok((hls_url, heartbeat))
+    }
+
+    /// セッションの生存期間が切れる前にキープアライブを送り続けるバックグラウンドタスクを起動する
+    ///
+    /// 生存期間の半分の間隔（[`HEARTBEAT_INTERVAL_RATIO`]）でキープアライブを送信し続ける。
+    /// 戻り値の [`HeartbeatGuard`] が drop されるとタスクは中断される
+    fn spawn_session_heartbeat(
+        &self,
+        video_id: &str,
+        session_id: String,
+        lifetime_secs: u64,
+    ) -> HeartbeatGuard {
+        let hc = self.hc.clone();
+        let fc_site_id = self.fc_site_id().unwrap_or("1".to_string());
+        let url = format!(
+            "{}/fc/video_pages/{}/session_ids/{}/heartbeat",
+            PREFIX, video_id, session_id
+        );
+        let interval = Duration::from_secs_f64((lifetime_secs as f64 * HEARTBEAT_INTERVAL_RATIO).max(1.0));
+
+        let handle = tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                let fc_site_id = fc_site_id.clone();
+                let result = hc
+                    .post_with_retry(
+                        &url,
+                        Some(&move |b| {
+                            b.json(&serde_json::json!({}))
+                                .header("Fc_site_id", fc_site_id.as_str())
+                        }),
+                    )
+                    .await;
+                if let Err(e) = result {
+                    log::warn!("Failed to send session heartbeat: {}", e);
+                }
+            }
+        });
+
+        HeartbeatGuard { handle }
+    }
+
+    /// HLS Master Playlist を取得し、`quality` に従って選択したバリアントの URI を返す
+    ///
+    /// `url` が既に Media Playlist を指している場合はそのまま返す
+    async fn resolve_variant_url(
+        &self,
+        url: &str,
+        quality: Quality,
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let resp = self.hc.get_with_retry(url, None).await?.error_for_status()?;
+        let payload = resp.bytes().await?;
+        let (_, pl) = m3u8_rs::parse_playlist(&payload)
+            .map_err(|e| format!("Failed to parse HLS playlist: {:?}", e))?;
+
+        match pl {
+            m3u8_rs::Playlist::MasterPlaylist(mp) => {
+                Ok(HLSDownloader::select_variant(&mp.variants, quality)
+                    .uri
+                    .as_str()
+                    .to_string())
+            }
+            m3u8_rs::Playlist::MediaPlaylist(_) => Ok(url.to_string()),
+        }
+    }
+
+    /// 動画の HLS Master Playlist から選択可能な画質バリアントを列挙する
+    ///
+    /// yt-dlp の niconico 抽出器が `qualities`/解像度情報を公開するのと同様、`download_video` の
+    /// `format_selector` に何を渡せるかを利用者が把握できるようにする
+    ///
+    /// # 引数
+    /// - `video_id`: 動画 ID
+    pub async fn list_formats(
+        &mut self,
+        video_id: &str,
+    ) -> Result<Vec<Format>, Box<dyn std::error::Error>> {
+        let (hls_url, _heartbeat) = self.get_video_hls_url(video_id).await?;
+        let resp = self
+            .hc
+            .get_with_retry(&hls_url, None)
+            .await?
+            .error_for_status()?;
+        let payload = resp.bytes().await?;
+        let (_, pl) = m3u8_rs::parse_playlist(&payload)
+            .map_err(|e| format!("Failed to parse HLS playlist: {:?}", e))?;
+
+        match pl {
+            m3u8_rs::Playlist::MasterPlaylist(mp) => Ok(mp
+                .variants
+                .into_iter()
+                .map(|v| Format {
+                    bandwidth: v.bandwidth,
+                    resolution: v.resolution.map(|r| (r.width, r.height)),
+                    codecs: v.codecs,
+                    uri: v.uri.as_str().to_string(),
+                })
+                .collect()),
+            // バリアントの選択肢がない Media Playlist は、単一フォーマットとして報告する
+            m3u8_rs::Playlist::MediaPlaylist(_) => Ok(vec![Format {
+                bandwidth: 0,
+                resolution: None,
+                codecs: None,
+                uri: hls_url,
+            }]),
+        }
+    }
+
+    /// HLS Master Playlist から字幕レンディション（`EXT-X-MEDIA TYPE=SUBTITLES`）を列挙する
+    ///
+    /// `hls_url` が Media Playlist を直接指している場合（レンディションの選択肢がない場合）は
+    /// 空の `Vec` を返す
+    ///
+    /// # 引数
+    /// - `hls_url`: HLS Master/Media Playlist の URL
+    async fn discover_subtitle_tracks(
+        &self,
+        hls_url: &str,
+    ) -> Result<Vec<SubtitleTrack>, Box<dyn std::error::Error>> {
+        let resp = self
+            .hc
+            .get_with_retry(hls_url, None)
+            .await?
+            .error_for_status()?;
+        let payload = resp.bytes().await?;
+        let (_, pl) = m3u8_rs::parse_playlist(&payload)
+            .map_err(|e| format!("Failed to parse HLS playlist: {:?}", e))?;
+
+        let mp = match pl {
+            m3u8_rs::Playlist::MasterPlaylist(mp) => mp,
+            // レンディションの選択肢がない Media Playlist には字幕トラックの情報がない
+            m3u8_rs::Playlist::MediaPlaylist(_) => return Ok(Vec::new()),
+        };
+
+        Ok(mp
+            .alternatives
+            .into_iter()
+            .filter(|a| matches!(a.media_type, m3u8_rs::AlternativeMediaType::Subtitles))
+            .filter_map(|a| {
+                Some(SubtitleTrack {
+                    url: a.uri?,
+                    language: a.language,
+                    title: Some(a.name),
+                    codec: SubtitleCodec::Srt,
+                })
+            })
+            .collect())
     }
 
     /// 動画をダウンロード
     ///
     /// 完全なフロー：
-    /// 1. 動画情報と HLS URL を取得
+    /// 1. 動画情報と HLS URL を取得し、Master Playlist の字幕レンディションを検出する
     /// 2. サムネイルをダウンロード
     /// 3. FFmpeg パラメータを準備（メタデータ、添付ファイルなど）
-    /// 4. HLS ダウンローダーを使用して動画ストリームをダウンロード
+    /// 4. 検出した字幕レンディションを `subtitles` として HLS ダウンローダーに渡し、動画ストリームを
+    ///    ダウンロード（`authenticated_url` が `.mpd` を指す場合は DASH ダウンローダーにフォールバック。
+    ///    この経路では字幕埋め込みと画質選択は未対応）
     /// 5. ファイルのタイムスタンプを設定
     ///
     /// # 引数
     /// - `video_id`: 動画 ID
     /// - `download_dir`: ダウンロードディレクトリ
+    /// - `format_selector`: 画質指定（`None` の場合、HLS ダウンローダーのデフォルト画質を使用）。
+    ///   [`list_formats`](Self::list_formats) で列挙したバリアントから選ぶことで、解像度の上限指定や
+    ///   音声のみのバリアント選択が可能になる
+    /// - `archive`: ダウンロード済みの `content_code` を記録するアーカイブ。`Some` の場合、既に
+    ///   記録済みであればダウンロードをスキップし、成功時には新たに記録する
     ///
     /// # エラー
-    /// 出力ファイルが既に存在する場合、`NicoChannelError::NCDFileExist` を返す
+    /// 出力ファイルが既に存在する場合、またはアーカイブに記録済みの場合、
+    /// `NicoChannelError::NCDFileExist` を返す
     pub async fn download_video(
         &mut self,
         video_id: &str,
         download_dir: impl AsRef<OsStr>,
+        format_selector: Option<Quality>,
+        archive: Option<&DownloadArchive>,
     ) -> Result<(), Box<dyn std::error::Error>> {
         // ===== ステップ 1: 動画情報と HLS URL を取得 =====
         let video_info = self.video_info(video_id).await?;
-        let hls_url = self.get_video_hls_url(video_id).await?;
-        let download_dir = PathBuf::from(download_dir.as_ref());
 
-        // 動画情報を抽出
-        let title = video_info["title"]
-            .as_str()
-            .ok_or("Failed to get title from video info")?;
-        let content_code = video_info["content_code"]
-            .as_str()
-            .ok_or("Failed to get content_code from video info")?;
+        // アーカイブに記録済みであれば、HLS URL の解決すら行わずにスキップする
+        if let Some(archive) = archive {
+            if archive.contains(&video_info.content_code).await {
+                return Err(Box::new(NicoChannelError::NCDFileExist));
+            }
+        }
+
+        // `_heartbeat` はこの関数を抜けるまで保持し、ダウンロード完了・エラーを問わず
+        // スコープを抜けた時点で自動的にキープアライブタスクを停止させる
+        let (hls_url, _heartbeat) = self.get_video_hls_url(video_id).await?;
+        // `format_selector` が指定されている場合のみ Master Playlist を解決し、
+        // 具体的なバリアント URI を HLS ダウンローダーへ渡す
+        let hls_url = match format_selector {
+            Some(quality) => self.resolve_variant_url(&hls_url, quality).await?,
+            None => hls_url,
+        };
+        // Master Playlist が広告する字幕レンディションを検出する（取得できなくても
+        // 致命的ではないため、失敗時は字幕なしとして続行する）
+        let mut subtitle_tracks = self
+            .discover_subtitle_tracks(&hls_url)
+            .await
+            .inspect_err(|e| log::warn!("Failed to discover subtitle tracks for {}: {}", video_id, e))
+            .unwrap_or_default();
+        let download_dir = PathBuf::from(download_dir.as_ref());
 
         // 出力ファイル名を生成
         let output_file = download_dir.join(format!(
             "{}.mkv",
-            Self::output_filename(title, content_code)
+            Self::output_filename(&video_info.title, &video_info.content_code)
         ));
 
         // ファイルが既に存在するか確認
@@ -329,13 +865,14 @@ impl NicoChannelClient {
 
         // ===== ステップ 2: サムネイルをダウンロード =====
         // 動画にサムネイル URL がない場合、チャンネルのデフォルトサムネイルを使用
-        let thumbnail_url = video_info["thumbnail_url"].as_str();
-        let thumbnail_url = match thumbnail_url {
+        let thumbnail_url = match video_info.thumbnail_url.as_deref() {
             Some(thumbnail_url) => thumbnail_url.to_string(),
             None => {
-                let fanclub_site_id = video_info["fanclub_site"]["id"]
-                    .as_i64()
-                    .ok_or("Failed to get fanclub_site id from video info")?;
+                let fanclub_site_id = video_info
+                    .fanclub_site
+                    .as_ref()
+                    .ok_or("Failed to get fanclub_site id from video info")?
+                    .id;
                 format!(
                     "https://cdn.nicochannel.jp/public_html/site_design/fanclub_sites/{}/thumbnail_image_path",
                     fanclub_site_id
@@ -350,6 +887,29 @@ impl NicoChannelClient {
             .download_with_retry(&thumbnail_url, thumbnail_path, true, None, None)
             .await?;
 
+        // ===== コメントを取得し、弾幕 ASS トラックとして動画の隣に書き出す =====
+        let comments = self
+            .download_comments(video_id, DEFAULT_MAX_COMMENTS)
+            .await
+            .inspect_err(|e| log::warn!("Failed to download comments for {}: {}", video_id, e))
+            .unwrap_or_default();
+        let ass_path = download_dir.join(format!(
+            "{}.ass",
+            Self::output_filename(&video_info.title, &video_info.content_code)
+        ));
+        if !comments.is_empty() {
+            let ass = danmaku::comments_to_ass(&comments, &DanmakuOptions::default());
+            tokio::fs::write(&ass_path, ass).await?;
+            if let Some(ass_path_str) = ass_path.to_str() {
+                subtitle_tracks.push(SubtitleTrack {
+                    url: format!("file://{}", ass_path_str),
+                    language: None,
+                    title: Some("Comments".to_string()),
+                    codec: SubtitleCodec::Ass,
+                });
+            }
+        }
+
         // ===== ステップ 3: FFmpeg パラメータを準備 =====
         let thumbnail_path_str = thumbnail_path
             .to_str()
@@ -363,18 +923,20 @@ impl NicoChannelClient {
             "copy".to_string(), // ストリームコピー（再エンコードしない）
         ];
 
-        // 動画メタデータを追加
-        let video_obj = video_info
-            .as_object()
-            .ok_or("Failed to get video info as object")?;
-        for (k, v) in video_obj.iter() {
-            if METADATA_KEY.contains(k.as_str()) {
+        // 動画メタデータを追加（型付きフィールドを直接読む）
+        let metadata: [(&str, Option<&str>); 7] = [
+            ("title", Some(video_info.title.as_str())),
+            ("content_code", Some(video_info.content_code.as_str())),
+            ("display_date", video_info.display_date.as_deref()),
+            ("description", video_info.description.as_deref()),
+            ("live_finished_at", video_info.live_finished_at.as_deref()),
+            ("released_at", video_info.released_at.as_deref()),
+            ("closed_at", video_info.closed_at.as_deref()),
+        ];
+        for (key, value) in metadata {
+            if let Some(value) = value {
                 ffmpeg_args.push("-metadata:g".to_string()); // グローバルメタデータ
-                let value = match v {
-                    serde_json::Value::String(s) => s.clone(),
-                    _ => v.to_string(),
-                };
-                ffmpeg_args.push(format!("{}={}", k, value));
+                ffmpeg_args.push(format!("{}={}", key, value));
             }
         }
 
@@ -388,14 +950,37 @@ impl NicoChannelClient {
 
         let ffmpeg_args_str: Vec<&str> = ffmpeg_args.iter().map(|s| s.as_str()).collect();
 
-        log::info!("Downloading video: {} to {}", title, output_file.display());
-
-        self.hls_downloader
-            .download(&hls_url, &output_file, Some(&ffmpeg_args_str))
-            .await?;
+        if Self::is_dash_manifest_url(&hls_url) {
+            // `authenticated_url` が HLS (m3u8) ではなく DASH (MPD) マニフェストを指している場合の
+            // フォールバック経路。字幕埋め込みと `--format` による画質選択は DASH 経路では未対応
+            if !subtitle_tracks.is_empty() {
+                log::warn!(
+                    "Subtitle embedding is not supported when downloading via DASH, skipping for {}",
+                    video_id
+                );
+            }
+            log::info!(
+                "Downloading video via DASH: {} to {}",
+                video_info.title,
+                output_file.display()
+            );
+            self.dash_downloader
+                .download(&hls_url, &output_file, Some(&ffmpeg_args_str))
+                .await?;
+        } else {
+            log::info!(
+                "Downloading video: {} to {}",
+                video_info.title,
+                output_file.display()
+            );
+            let subtitles = (!subtitle_tracks.is_empty()).then_some(subtitle_tracks.as_slice());
+            self.hls_downloader
+                .download(&hls_url, &output_file, Some(&ffmpeg_args_str), subtitles)
+                .await?;
+        }
 
         // ファイルのタイムスタンプを設定
-        if let Some(display_date) = video_info["display_date"].as_str() {
+        if let Some(ref display_date) = video_info.display_date {
             if let Ok(tm) = chrono::NaiveDateTime::parse_from_str(display_date, "%Y-%m-%d %H:%M:%S")
             {
                 let timestamp = tm.and_utc().timestamp();
@@ -407,6 +992,141 @@ impl NicoChannelClient {
             }
         }
 
+        // ダウンロードに成功したので、アーカイブに記録する
+        if let Some(archive) = archive {
+            archive.record(&video_info.content_code).await?;
+        }
+
+        Ok(())
+    }
+
+    /// チャンネル内のすべての動画をダウンロードする
+    ///
+    /// [`Self::video_pages_stream`] を通じてページ単位で遅延取得するため、チャンネル全体を
+    /// 一度にバッファしない。`filter` に一致しない動画はスキップする。会員限定・有料限定で
+    /// 無料期間のない動画もスキップし、`archive_path` が指定されていればダウンロード済みの
+    /// 動画（`content_code` で判定）も再ダウンロードしない
+    ///
+    /// # 引数
+    /// - `channel_id`: チャンネル ID
+    /// - `download_dir`: ダウンロードディレクトリ
+    /// - `archive_path`: ダウンロード済みの `content_code` を記録するアーカイブファイル
+    ///   （`None` の場合はアーカイブを使用しない）
+    /// - `filter`: 日付範囲・コンテンツ種別による絞り込み条件
+    pub async fn download_channel(
+        &mut self,
+        channel_id: i64,
+        download_dir: impl AsRef<OsStr>,
+        archive_path: Option<&Path>,
+        filter: VideoPageFilter,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let archive = match archive_path {
+            Some(path) => Some(DownloadArchive::load(path).await?),
+            None => None,
+        };
+        let download_dir = download_dir.as_ref().to_owned();
+
+        let mut pages = Box::pin(self.video_pages_stream(channel_id, filter));
+        while let Some(video) = pages.next().await {
+            let video = video?;
+
+            // 動画タイプのみを処理（video_media_type.id == 1）
+            if video.video_media_type.as_ref().map(|t| t.id) != Some(1) {
+                continue;
+            }
+
+            // 視聴権限を確認：会員限定・有料限定（delivery_target_id: 1, 3）で
+            // 無料期間が設定されていない場合はスキップ
+            let delivery_target_id = video.video_delivery_target.as_ref().map(|t| t.id);
+            if (delivery_target_id == Some(1) || delivery_target_id == Some(3))
+                && video.video_free_periods.is_none()
+            {
+                continue;
+            }
+
+            match self
+                .download_video(&video.content_code, &download_dir, None, archive.as_ref())
+                .await
+            {
+                Ok(_) => log::info!("Successfully downloaded video {}", video.content_code),
+                Err(e) => match e.downcast_ref::<NicoChannelError>() {
+                    Some(NicoChannelError::NCDFileExist) => {
+                        log::info!("Video {} already downloaded, skipping", video.content_code);
+                    }
+                    Some(NicoChannelError::MembershipRequired(_)) => {
+                        log::warn!(
+                            "Video {} requires a membership or purchase, skipping: {}",
+                            video.content_code,
+                            e
+                        );
+                    }
+                    None => {
+                        log::error!("Error downloading video {}: {}", video.content_code, e);
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 動画のメタデータを正規化し、ダウンロードを行わずに1行の JSON として標準出力に出力する
+    ///
+    /// yt-dlp の `--dump-json`/`--simulate` に相当する、スクリプトから扱いやすい出力を提供する
+    ///
+    /// # 引数
+    /// - `output_dir`: ダウンロード先として想定する出力ディレクトリ（ファイル名の算出にのみ使用）
+    /// - `video_id`: 動画 ID
+    pub async fn dump_json(
+        &mut self,
+        output_dir: &Path,
+        video_id: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let video_info = self.video_info(video_id).await?;
+        let output_filename = Self::output_filename(&video_info.title, &video_info.content_code);
+
+        // HLS URL の解決はセッション発行を伴うため、失敗しても致命的エラーとはせず `None` を報告する。
+        // ダウンロードは行わないため、キープアライブガードはここで即座に破棄してよい
+        let hls_url = self
+            .get_video_hls_url(video_id)
+            .await
+            .ok()
+            .map(|(url, _heartbeat)| url);
+
+        let resolved = ResolvedVideoPage {
+            page: (*video_info).clone(),
+            hls_url,
+            output_filename: output_dir
+                .join(format!("{}.mkv", output_filename))
+                .display()
+                .to_string(),
+        };
+        println!("{}", serde_json::to_string(&resolved)?);
         Ok(())
     }
 }
+
+/// HLS Master Playlist 内のバリアントストリーム1つ分の情報（[`NicoChannelClient::list_formats`] の戻り値）
+#[derive(Debug, Clone, Serialize)]
+pub struct Format {
+    /// 帯域幅（bps）
+    pub bandwidth: u64,
+    /// 解像度（幅, 高さ）。音声のみのバリアントでは `None` になることがある
+    pub resolution: Option<(u64, u64)>,
+    /// コーデック文字列（例: `"avc1.64001f,mp4a.40.2"`）
+    pub codecs: Option<String>,
+    /// バリアントの Media Playlist URI
+    pub uri: String,
+}
+
+/// 完全に解決された動画メタデータ（`dump_json` の出力形式）
+///
+/// `VideoPage` のフィールドをそのまま展開した上に、解決済みの HLS URL と出力予定のファイル名を加える
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedVideoPage {
+    #[serde(flatten)]
+    pub page: VideoPage,
+    /// 解決済みの HLS (M3U8) ストリーム URL。取得に失敗した場合は `None`
+    pub hls_url: Option<String>,
+    pub output_filename: String,
+}
@@ -0,0 +1,148 @@
+//! ニコニコ風の弾幕コメントを ASS 字幕トラックへ変換
+//!
+//! 各コメントは再生位置を開始時刻とし、`\move` タグで画面右端から左端へ流れる
+//! `Dialogue` として配置される。既に配置済みのコメントの速度・開始時刻を追跡し、
+//! 追いつき（追突）が起きないレーンを選んで重なりを避ける
+
+use crate::nicochannel::model::Comment;
+
+/// コメントが画面を流れきるまでの時間（秒）
+const SCROLL_DURATION_SECS: f64 = 4.0;
+/// 1レーンあたりの高さ（px）
+const LANE_HEIGHT_PX: u32 = 36;
+/// 文字幅の概算係数（フォントサイズに対する比率）
+const CHAR_WIDTH_RATIO: f64 = 0.6;
+
+/// ASS 弾幕トラックの描画設定
+#[derive(Debug, Clone, Copy)]
+pub struct DanmakuOptions {
+    /// 画面の幅（px）
+    pub screen_width: u32,
+    /// 画面の高さ（px）
+    pub screen_height: u32,
+    /// フォントサイズ（px）
+    pub font_size: u32,
+}
+
+impl Default for DanmakuOptions {
+    fn default() -> Self {
+        Self {
+            screen_width: 1920,
+            screen_height: 1080,
+            font_size: 32,
+        }
+    }
+}
+
+/// 配置済みコメントのレーン追跡情報
+struct LaneOccupant {
+    /// 流れ始めた時刻（秒）
+    start_secs: f64,
+    /// 右端から左端まで移動する速度（px/秒）
+    speed: f64,
+}
+
+/// コメント本文のピクセル幅を概算する
+fn estimate_text_width(text: &str, font_size: u32) -> f64 {
+    text.chars().count() as f64 * font_size as f64 * CHAR_WIDTH_RATIO
+}
+
+/// 秒数を ASS の時刻表記（H:MM:SS.cc）に変換する
+fn format_ass_time(total_secs: f64) -> String {
+    let total_secs = total_secs.max(0.0);
+    let hours = (total_secs / 3600.0) as u64;
+    let minutes = ((total_secs % 3600.0) / 60.0) as u64;
+    let secs = total_secs % 60.0;
+    format!("{}:{:02}:{:05.2}", hours, minutes, secs)
+}
+
+/// 新しいコメントが既存レーンの先行コメントと重ならないかを判定する
+///
+/// 両者は同じ地点（画面右端）から出発する。先行コメントの末尾が画面右端を通過し終える前に
+/// 出発すると、その時点で重なる。後発のコメントの方が速く流れる場合は、先行コメントの末尾が
+/// 画面左端（x=0）に達する時刻（`start_secs + SCROLL_DURATION_SECS`）に後発コメントの先頭が
+/// まだ画面内（x>=0）にあれば、途中で追いつくことはなく安全に共用できる
+fn lane_is_safe(occupant: &LaneOccupant, new_start: f64, new_speed: f64, screen_width: f64) -> bool {
+    // 先行コメントの幅は、流れる距離（画面幅 + 幅）から逆算できる
+    let occupant_width = occupant.speed * SCROLL_DURATION_SECS - screen_width;
+    if occupant.start_secs + occupant_width / occupant.speed > new_start {
+        return false;
+    }
+    if new_speed <= occupant.speed {
+        // 後発のコメントが先行コメントより遅い（か同速）なら、差は開く一方で追いつかない
+        return true;
+    }
+    new_speed * (occupant.start_secs + SCROLL_DURATION_SECS - new_start) <= screen_width
+}
+
+/// コメント列を ASS 形式の弾幕字幕トラックへレンダリングする
+///
+/// `comments` は再生位置の昇順でなくてもよい（内部でソートする）
+pub fn comments_to_ass(comments: &[Comment], options: &DanmakuOptions) -> String {
+    let mut sorted: Vec<&Comment> = comments.iter().collect();
+    sorted.sort_by(|a, b| a.vpos_sec.total_cmp(&b.vpos_sec));
+
+    let max_lanes = (options.screen_height / LANE_HEIGHT_PX).max(1);
+    let mut lanes: Vec<Option<LaneOccupant>> = (0..max_lanes).map(|_| None).collect();
+
+    let mut dialogues = String::new();
+    for comment in sorted {
+        let text_width = estimate_text_width(&comment.body, options.font_size);
+        let speed = (options.screen_width as f64 + text_width) / SCROLL_DURATION_SECS;
+
+        // 追いつきが起きない最初のレーンを選ぶ。全レーンが埋まっている場合は先頭レーンにフォールバックする
+        let lane = lanes
+            .iter()
+            .position(|occupant| match occupant {
+                None => true,
+                Some(occupant) => {
+                    lane_is_safe(occupant, comment.vpos_sec, speed, options.screen_width as f64)
+                }
+            })
+            .unwrap_or(0);
+        lanes[lane] = Some(LaneOccupant {
+            start_secs: comment.vpos_sec,
+            speed,
+        });
+
+        let y = lane as u32 * LANE_HEIGHT_PX + LANE_HEIGHT_PX / 2;
+        let start = comment.vpos_sec;
+        let end = start + SCROLL_DURATION_SECS;
+        let x_start = options.screen_width as f64;
+        let x_end = -text_width;
+
+        dialogues.push_str(&format!(
+            "Dialogue: 0,{},{},Default,,0,0,0,,{{\\move({:.0},{},{:.0},{})}}{}\n",
+            format_ass_time(start),
+            format_ass_time(end),
+            x_start,
+            y,
+            x_end,
+            y,
+            comment.body.replace('\n', "\\N"),
+        ));
+    }
+
+    format!(
+        "[Script Info]\n\
+         Title: NicoChannel comments\n\
+         ScriptType: v4.00+\n\
+         WrapStyle: 2\n\
+         ScaledBorderAndShadow: yes\n\
+         PlayResX: {width}\n\
+         PlayResY: {height}\n\
+         Collisions: Normal\n\
+         \n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Default,Noto Sans CJK JP,{font_size},&H00FFFFFF,&H000000FF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,2,0,7,0,0,0,1\n\
+         \n\
+         [Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+         {dialogues}",
+        width = options.screen_width,
+        height = options.screen_height,
+        font_size = options.font_size,
+        dialogues = dialogues,
+    )
+}
@@ -0,0 +1,78 @@
+//! NicoChannel API の型付きデータモデル
+//!
+//! API レスポンスは raw な `serde_json::Value` ではなく、ここで定義する構造体にデシリアライズされる。
+//! 未知のフィールドは単に無視され、欠落している任意フィールドは `#[serde(default)]` で `None` となる
+
+use serde::{Deserialize, Serialize};
+
+/// HLS ストリーム情報
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VideoStream {
+    /// `{session_id}` プレースホルダーを含む HLS (M3U8) の URL テンプレート
+    pub authenticated_url: String,
+}
+
+/// 動画の配信対象（1=会員限定, 3=有料限定 など）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeliveryTarget {
+    pub id: i64,
+}
+
+/// 動画の種別（1=通常動画 など）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VideoMediaType {
+    pub id: i64,
+}
+
+/// 動画が属するファンクラブサイト情報
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FanclubSite {
+    pub id: i64,
+}
+
+/// 動画ページ
+///
+/// 一覧取得 (`video_pages`) と詳細取得 (`video_info`) の両方のレスポンスに共通して使われる
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VideoPage {
+    pub title: String,
+    pub content_code: String,
+    #[serde(default)]
+    pub display_date: Option<String>,
+    #[serde(default)]
+    pub released_at: Option<String>,
+    #[serde(default)]
+    pub live_finished_at: Option<String>,
+    #[serde(default)]
+    pub closed_at: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub thumbnail_url: Option<String>,
+    #[serde(default)]
+    pub video_stream: Option<VideoStream>,
+    #[serde(default)]
+    pub video_media_type: Option<VideoMediaType>,
+    #[serde(default)]
+    pub video_delivery_target: Option<DeliveryTarget>,
+    /// 限定配信動画でも無料視聴期間が設定されている場合に値を持つ
+    #[serde(default)]
+    pub video_free_periods: Option<serde_json::Value>,
+    #[serde(default)]
+    pub fanclub_site: Option<FanclubSite>,
+}
+
+/// チャンネル（ファンクラブサイト）情報
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ChannelInfo {
+    pub id: i64,
+}
+
+/// 視聴者コメント（ニコニコ風の弾幕コメント）
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Comment {
+    /// コメント本文
+    pub body: String,
+    /// 投稿された動画の再生位置（秒）
+    pub vpos_sec: f64,
+}